@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS sessions (
+    token_hash TEXT PRIMARY KEY NOT NULL, /* sha256 of the token handed to the client */
+    user_id INT NOT NULL REFERENCES users ON DELETE CASCADE,
+    created TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id serial PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE, /* sha256 of the key, the key itself is only shown once */
+    prefix TEXT NOT NULL,
+    created_by INT NOT NULL REFERENCES users ON DELETE CASCADE,
+    scopes TEXT[] NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT NOW(),
+    revoked TIMESTAMP
+);
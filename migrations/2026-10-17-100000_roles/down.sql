@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS permissions (
+    user_id INT PRIMARY KEY NOT NULL REFERENCES users,
+    admin BOOLEAN NOT NULL,
+    view_pending BOOLEAN NOT NULL,
+    view_received BOOLEAN NOT NULL,
+    edit_pending BOOLEAN NOT NULL,
+    create_orders BOOLEAN NOT NULL,
+    edit_received BOOLEAN NOT NULL,
+    view_products BOOLEAN NOT NULL,
+    edit_products BOOLEAN NOT NULL,
+    remove_orders BOOLEAN NOT NULL,
+    view_suppliers BOOLEAN NOT NULL
+);
+
+INSERT INTO permissions
+SELECT
+    users.id,
+    COALESCE(bool_or(role_permissions.permission = 'admin'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'view_pending'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'view_received'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'edit_pending'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'create_orders'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'edit_received'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'view_products'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'edit_products'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'remove_orders'), FALSE),
+    COALESCE(bool_or(role_permissions.permission = 'view_suppliers'), FALSE)
+FROM users
+LEFT JOIN user_roles ON user_roles.user_id = users.id
+LEFT JOIN role_permissions ON role_permissions.role_id = user_roles.role_id
+GROUP BY users.id;
+
+DROP TABLE user_roles;
+DROP TABLE role_permissions;
+DROP TABLE roles;
@@ -0,0 +1,91 @@
+CREATE TABLE IF NOT EXISTS roles (
+    id serial PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role_id INT NOT NULL REFERENCES roles ON DELETE CASCADE,
+    permission TEXT NOT NULL,
+    PRIMARY KEY (role_id, permission)
+);
+
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id INT NOT NULL REFERENCES users ON DELETE CASCADE,
+    role_id INT NOT NULL REFERENCES roles ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_id)
+);
+
+INSERT INTO roles (name) VALUES ('Admin'), ('Cashier'), ('Receiver'), ('Buyer'), ('Manager');
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT roles.id, defaults.permission
+FROM roles
+JOIN (VALUES
+    ('Admin', 'admin'),
+    ('Admin', 'view_pending'),
+    ('Admin', 'view_received'),
+    ('Admin', 'edit_pending'),
+    ('Admin', 'create_orders'),
+    ('Admin', 'edit_received'),
+    ('Admin', 'remove_orders'),
+    ('Admin', 'edit_products'),
+    ('Admin', 'view_products'),
+    ('Admin', 'view_suppliers'),
+    ('Cashier', 'view_products'),
+    ('Receiver', 'view_products'),
+    ('Receiver', 'view_pending'),
+    ('Receiver', 'view_received'),
+    ('Receiver', 'edit_received'),
+    ('Buyer', 'view_products'),
+    ('Buyer', 'view_suppliers'),
+    ('Buyer', 'view_pending'),
+    ('Buyer', 'create_orders'),
+    ('Buyer', 'edit_pending'),
+    ('Buyer', 'remove_orders'),
+    ('Manager', 'view_pending'),
+    ('Manager', 'view_received'),
+    ('Manager', 'edit_pending'),
+    ('Manager', 'create_orders'),
+    ('Manager', 'edit_received'),
+    ('Manager', 'remove_orders'),
+    ('Manager', 'edit_products'),
+    ('Manager', 'view_products'),
+    ('Manager', 'view_suppliers')
+) AS defaults (role, permission) ON defaults.role = roles.name;
+
+/* Existing users keep exactly the flags they had: each one gets a role of their own */
+CREATE TEMPORARY TABLE legacy_permissions AS
+SELECT permissions.user_id, flags.permission
+FROM permissions
+CROSS JOIN LATERAL (VALUES
+    ('admin', permissions.admin),
+    ('view_pending', permissions.view_pending),
+    ('view_received', permissions.view_received),
+    ('edit_pending', permissions.edit_pending),
+    ('create_orders', permissions.create_orders),
+    ('edit_received', permissions.edit_received),
+    ('remove_orders', permissions.remove_orders),
+    ('edit_products', permissions.edit_products),
+    ('view_products', permissions.view_products),
+    ('view_suppliers', permissions.view_suppliers)
+) AS flags (permission, granted)
+WHERE flags.granted;
+
+INSERT INTO roles (name)
+SELECT DISTINCT 'Migrated: ' || users.name || ' (#' || users.id || ')'
+FROM users
+JOIN legacy_permissions ON legacy_permissions.user_id = users.id;
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT roles.id, legacy_permissions.permission
+FROM legacy_permissions
+JOIN users ON users.id = legacy_permissions.user_id
+JOIN roles ON roles.name = 'Migrated: ' || users.name || ' (#' || users.id || ')';
+
+INSERT INTO user_roles (user_id, role_id)
+SELECT users.id, roles.id
+FROM users
+JOIN roles ON roles.name = 'Migrated: ' || users.name || ' (#' || users.id || ')';
+
+DROP TABLE legacy_permissions;
+DROP TABLE permissions;
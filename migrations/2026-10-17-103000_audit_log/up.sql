@@ -0,0 +1,16 @@
+CREATE TYPE audit_action AS ENUM ('insert', 'update', 'delete');
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id serial PRIMARY KEY NOT NULL,
+    user_id INT REFERENCES users ON DELETE SET NULL, /* NULL once the acting user is deleted */
+    occurred_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    entity TEXT NOT NULL,
+    entity_id INT NOT NULL,
+    action audit_action NOT NULL,
+    before JSONB,
+    after JSONB
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_entity_idx ON audit_log (entity, entity_id);
+CREATE INDEX IF NOT EXISTS audit_log_user_id_idx ON audit_log (user_id);
+CREATE INDEX IF NOT EXISTS audit_log_occurred_at_idx ON audit_log (occurred_at);
@@ -0,0 +1,2 @@
+DROP TABLE stock_movements;
+DROP TYPE stock_movement_reason;
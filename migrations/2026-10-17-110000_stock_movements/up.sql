@@ -0,0 +1,17 @@
+CREATE TYPE stock_movement_reason AS ENUM ('receipt', 'sale', 'adjustment', 'damage', 'transfer', 'return');
+
+CREATE TABLE IF NOT EXISTS stock_movements (
+    id serial PRIMARY KEY NOT NULL,
+    product_id INT NOT NULL REFERENCES products ON DELETE CASCADE,
+    quantity FLOAT NOT NULL, /* Signed change to the product's amount */
+    reason stock_movement_reason NOT NULL,
+    order_id INT, /* The received order this came from. Not a foreign key, the order may be unreceived later */
+    user_id INT REFERENCES users ON DELETE SET NULL,
+    occurred_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS stock_movements_product_id_idx ON stock_movements (product_id, occurred_at);
+
+/* Opening balances, so every product's amount is the sum of its movements */
+INSERT INTO stock_movements (product_id, quantity, reason)
+SELECT id, amount, 'adjustment' FROM products WHERE amount <> 0;
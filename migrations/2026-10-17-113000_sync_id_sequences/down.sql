@@ -0,0 +1,2 @@
+/* Nothing to undo, the sequences only ever move forward */
+SELECT 1;
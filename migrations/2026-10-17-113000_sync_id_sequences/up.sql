@@ -0,0 +1,9 @@
+/* Ids used to be allocated as max(id) + 1 by the application, which never
+   advanced the serial sequences. Move each one past the ids already taken. */
+SELECT setval(pg_get_serial_sequence('users', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM users;
+SELECT setval(pg_get_serial_sequence('products', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM products;
+SELECT setval(pg_get_serial_sequence('categories', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM categories;
+SELECT setval(pg_get_serial_sequence('suppliers', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM suppliers;
+SELECT setval(pg_get_serial_sequence('brands', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM brands;
+SELECT setval(pg_get_serial_sequence('pending_orders', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM pending_orders;
+SELECT setval(pg_get_serial_sequence('received_orders', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM received_orders;
@@ -0,0 +1,21 @@
+ALTER TABLE categories ADD COLUMN products INT[] NOT NULL DEFAULT '{}';
+ALTER TABLE suppliers ADD COLUMN products INT[] NOT NULL DEFAULT '{}';
+ALTER TABLE brands ADD COLUMN products INT[] NOT NULL DEFAULT '{}';
+
+UPDATE categories SET products = ARRAY(
+    SELECT product_id FROM product_categories WHERE category_id = categories.id
+);
+UPDATE suppliers SET products = ARRAY(
+    SELECT product_id FROM product_suppliers WHERE supplier_id = suppliers.id
+);
+UPDATE brands SET products = ARRAY(
+    SELECT id FROM products WHERE brand_id = brands.id
+);
+
+ALTER TABLE categories ALTER COLUMN products DROP DEFAULT;
+ALTER TABLE suppliers ALTER COLUMN products DROP DEFAULT;
+ALTER TABLE brands ALTER COLUMN products DROP DEFAULT;
+
+ALTER TABLE products DROP COLUMN brand_id;
+DROP TABLE product_suppliers;
+DROP TABLE product_categories;
@@ -0,0 +1,37 @@
+CREATE TABLE IF NOT EXISTS product_categories (
+    product_id INT NOT NULL REFERENCES products ON DELETE CASCADE,
+    category_id INT NOT NULL REFERENCES categories ON DELETE CASCADE,
+    PRIMARY KEY (product_id, category_id)
+);
+
+CREATE TABLE IF NOT EXISTS product_suppliers (
+    product_id INT NOT NULL REFERENCES products ON DELETE CASCADE,
+    supplier_id INT NOT NULL REFERENCES suppliers ON DELETE CASCADE,
+    PRIMARY KEY (product_id, supplier_id)
+);
+
+CREATE INDEX IF NOT EXISTS product_categories_category_id_idx ON product_categories (category_id);
+CREATE INDEX IF NOT EXISTS product_suppliers_supplier_id_idx ON product_suppliers (supplier_id);
+
+ALTER TABLE products ADD COLUMN brand_id INT REFERENCES brands ON DELETE SET NULL;
+
+/* Carry over the membership arrays, dropping duplicates, NULLs and ids of
+   products that no longer exist */
+INSERT INTO product_categories (product_id, category_id)
+SELECT DISTINCT member.product_id, categories.id
+FROM categories, unnest(categories.products) AS member(product_id)
+WHERE member.product_id IN (SELECT id FROM products);
+
+INSERT INTO product_suppliers (product_id, supplier_id)
+SELECT DISTINCT member.product_id, suppliers.id
+FROM suppliers, unnest(suppliers.products) AS member(product_id)
+WHERE member.product_id IN (SELECT id FROM products);
+
+/* A product listed under several brands keeps the oldest one */
+UPDATE products SET brand_id = (
+    SELECT MIN(brands.id) FROM brands WHERE products.id = ANY(brands.products)
+);
+
+ALTER TABLE categories DROP COLUMN products;
+ALTER TABLE suppliers DROP COLUMN products;
+ALTER TABLE brands DROP COLUMN products;
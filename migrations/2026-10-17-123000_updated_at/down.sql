@@ -0,0 +1,15 @@
+DROP TRIGGER set_updated_at ON users;
+DROP TRIGGER set_updated_at ON products;
+DROP TRIGGER set_updated_at ON categories;
+DROP TRIGGER set_updated_at ON suppliers;
+DROP TRIGGER set_updated_at ON brands;
+DROP TRIGGER set_updated_at ON pending_orders;
+DROP TRIGGER set_updated_at ON received_orders;
+
+ALTER TABLE users DROP COLUMN updated_at;
+ALTER TABLE products DROP COLUMN updated_at;
+ALTER TABLE categories DROP COLUMN updated_at;
+ALTER TABLE suppliers DROP COLUMN updated_at;
+ALTER TABLE brands DROP COLUMN updated_at;
+ALTER TABLE pending_orders DROP COLUMN updated_at;
+ALTER TABLE received_orders DROP COLUMN updated_at;
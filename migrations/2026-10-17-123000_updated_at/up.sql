@@ -0,0 +1,17 @@
+/* Lets clients detect concurrent edits. The trigger from the initial setup
+   bumps updated_at on every update that changes the row. */
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE products ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE categories ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE suppliers ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE brands ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE pending_orders ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE received_orders ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+
+SELECT diesel_manage_updated_at('users');
+SELECT diesel_manage_updated_at('products');
+SELECT diesel_manage_updated_at('categories');
+SELECT diesel_manage_updated_at('suppliers');
+SELECT diesel_manage_updated_at('brands');
+SELECT diesel_manage_updated_at('pending_orders');
+SELECT diesel_manage_updated_at('received_orders');
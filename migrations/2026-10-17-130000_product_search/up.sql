@@ -0,0 +1,7 @@
+/* Full-text index over products. PRODUCT_DOCUMENT in models.rs repeats this
+   expression, which searches have to match for Postgres to use the index */
+CREATE INDEX IF NOT EXISTS products_search_idx ON products USING GIN ((
+    setweight(to_tsvector('simple', upc), 'A') ||
+    setweight(to_tsvector('english', name), 'A') ||
+    setweight(to_tsvector('english', description), 'B')
+));
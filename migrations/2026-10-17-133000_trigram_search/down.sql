@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS brands_name_trgm_idx;
+DROP INDEX IF EXISTS suppliers_name_trgm_idx;
+DROP INDEX IF EXISTS products_name_trgm_idx;
+
+DROP EXTENSION IF EXISTS pg_trgm;
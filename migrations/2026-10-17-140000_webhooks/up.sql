@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS webhooks (
+    id serial PRIMARY KEY NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL, /* kept in plain text since every delivery is signed with it */
+    events TEXT[] NOT NULL,
+    created_by INT REFERENCES users ON DELETE SET NULL, /* NULL once the user is deleted */
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id serial PRIMARY KEY NOT NULL,
+    webhook_id INT NOT NULL REFERENCES webhooks ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt TIMESTAMP DEFAULT NOW(), /* NULL once delivered or given up on */
+    delivered TIMESTAMP,
+    last_status INT,
+    last_error TEXT,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id, id);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx ON webhook_deliveries (next_attempt)
+    WHERE next_attempt IS NOT NULL;
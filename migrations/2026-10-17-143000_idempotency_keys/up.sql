@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    user_id INT NOT NULL REFERENCES users ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    request TEXT NOT NULL, /* Method, path and body hash the key was first sent with */
+    response JSONB, /* Set in the same transaction, so only NULL while the first request runs */
+    created TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_created_idx ON idempotency_keys (created);
@@ -0,0 +1,18 @@
+ALTER TABLE received_orders
+    DROP CONSTRAINT IF EXISTS received_orders_damaged_check,
+    DROP CONSTRAINT IF EXISTS received_orders_actually_received_check,
+    DROP CONSTRAINT IF EXISTS received_orders_gross_amount_check;
+
+ALTER TABLE pending_orders DROP CONSTRAINT IF EXISTS pending_orders_amount_check;
+
+ALTER TABLE products
+    DROP CONSTRAINT IF EXISTS products_buy_level_check,
+    DROP CONSTRAINT IF EXISTS products_sale_price_check,
+    DROP CONSTRAINT IF EXISTS products_selling_price_per_unit_check,
+    DROP CONSTRAINT IF EXISTS products_cost_price_per_unit_check,
+    DROP CONSTRAINT IF EXISTS products_case_size_check,
+    DROP CONSTRAINT IF EXISTS products_amount_check,
+    DROP CONSTRAINT IF EXISTS products_upc_key;
+
+DROP INDEX IF EXISTS users_email_key;
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_name_key;
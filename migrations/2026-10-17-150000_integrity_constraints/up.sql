@@ -0,0 +1,21 @@
+/* Fails on duplicates or out of range values already in the database, which
+   have to be cleaned up by hand first */
+ALTER TABLE users ADD CONSTRAINT users_name_key UNIQUE (name);
+/* Users may be created without an email */
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_key ON users (email) WHERE email <> '';
+
+ALTER TABLE products
+    ADD CONSTRAINT products_upc_key UNIQUE (upc),
+    ADD CONSTRAINT products_amount_check CHECK (amount >= 0),
+    ADD CONSTRAINT products_case_size_check CHECK (case_size > 0),
+    ADD CONSTRAINT products_cost_price_per_unit_check CHECK (cost_price_per_unit >= 0),
+    ADD CONSTRAINT products_selling_price_per_unit_check CHECK (selling_price_per_unit >= 0),
+    ADD CONSTRAINT products_sale_price_check CHECK (sale_price >= 0),
+    ADD CONSTRAINT products_buy_level_check CHECK (buy_level >= 0);
+
+ALTER TABLE pending_orders ADD CONSTRAINT pending_orders_amount_check CHECK (amount >= 0);
+
+ALTER TABLE received_orders
+    ADD CONSTRAINT received_orders_gross_amount_check CHECK (gross_amount >= 0),
+    ADD CONSTRAINT received_orders_actually_received_check CHECK (actually_received >= 0),
+    ADD CONSTRAINT received_orders_damaged_check CHECK (damaged >= 0 AND damaged <= actually_received);
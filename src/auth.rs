@@ -0,0 +1,375 @@
+use crate::error::{ApiError, ApiResult};
+use crate::models::{ApiKey, Session, User};
+use crate::permissions::{self, PermissionSet};
+use crate::services;
+use crate::ServerState;
+use bcrypt::verify;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rocket::serde::json::Json;
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    State,
+};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+const SESSION_LENGTH_HOURS: i64 = 12;
+const ACCESS_TOKEN_LENGTH_MINUTES: i64 = 5;
+
+/// Keys used to sign and verify access tokens. Every instance sharing the same
+/// `TOKEN_SECRET` accepts tokens issued by the others.
+pub struct TokenKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl TokenKeys {
+    pub fn from_secret(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::from_secret(
+            env::var("TOKEN_SECRET")
+                .expect("TOKEN_SECRET must be set")
+                .as_bytes(),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: i32,
+    pub iat: i64,
+    pub exp: i64,
+    pub permissions: PermissionSet,
+}
+
+pub fn issue_access_token(
+    keys: &TokenKeys,
+    user_id: i32,
+    permissions: PermissionSet,
+) -> ApiResult<(String, NaiveDateTime)> {
+    let now = Utc::now().naive_utc();
+    let expires = now + Duration::minutes(ACCESS_TOKEN_LENGTH_MINUTES);
+    let claims = Claims {
+        sub: user_id,
+        iat: now.timestamp(),
+        exp: expires.timestamp(),
+        permissions,
+    };
+    let token = encode(&Header::default(), &claims, &keys.encoding)
+        .map_err(|error| ApiError::Internal(error.to_string()))?;
+    Ok((token, expires))
+}
+
+#[derive(Debug)]
+pub struct AuthGuard {
+    pub user_id: i32,
+    pub permissions: PermissionSet,
+}
+
+/// The credential a request was sent with. Long-lived responses, such as event
+/// streams, keep it to check again later.
+pub enum Credential {
+    Bearer(String),
+    ApiKey(String),
+}
+
+impl Credential {
+    /// Whom the credential belongs to and what they may do now, or `None` once
+    /// it has expired or been revoked.
+    pub async fn authenticate(&self, state: &ServerState) -> ApiResult<Option<AuthGuard>> {
+        match self {
+            // Signed access tokens carry everything we need, so they are checked
+            // without touching the database. Refresh tokens are only accepted by
+            // `refresh` and `logout`.
+            Credential::Bearer(token) => {
+                Ok(
+                    decode::<Claims>(token, &state.token_keys.decoding, &Validation::default())
+                        .ok()
+                        .map(|data| AuthGuard {
+                            user_id: data.claims.sub,
+                            permissions: data.claims.permissions,
+                        }),
+                )
+            }
+            Credential::ApiKey(key) => {
+                let mut conn = state.db_pool.get().await?;
+                Ok(from_api_key(conn.as_mut(), key).await?)
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Credential {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = match req.headers().get_one("Authorization") {
+            Some(header) => header,
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let credential = if let Some(token) = header.strip_prefix("Bearer ") {
+            Credential::Bearer(token.trim().to_string())
+        } else if let Some(key) = header.strip_prefix("ApiKey ") {
+            Credential::ApiKey(key.trim().to_string())
+        } else {
+            return Outcome::Failure((Status::Unauthorized, ()));
+        };
+        Outcome::Success(credential)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthGuard {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let credential = match req.guard::<Credential>().await {
+            Outcome::Success(credential) => credential,
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(forward) => return Outcome::Forward(forward),
+        };
+        let state = req.rocket().state::<ServerState>().unwrap();
+        match credential.authenticate(state).await {
+            Ok(Some(auth)) => Outcome::Success(auth),
+            Ok(None) => Outcome::Failure((Status::Unauthorized, ())),
+            Err(error) => Outcome::Failure((error.status(), ())),
+        }
+    }
+}
+
+/// Declares a request guard that succeeds only for credentials holding the
+/// given permission. Missing credentials give a 401, a missing permission a 403.
+macro_rules! permission_guard {
+    ($($guard:ident => $permission:expr),* $(,)?) => {
+        $(
+            #[derive(Debug)]
+            pub struct $guard(pub AuthGuard);
+
+            impl $guard {
+                pub fn user_id(&self) -> i32 {
+                    self.0.user_id
+                }
+            }
+
+            #[rocket::async_trait]
+            impl<'r> FromRequest<'r> for $guard {
+                type Error = ();
+
+                async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+                    match req.guard::<AuthGuard>().await {
+                        Outcome::Success(auth) if auth.permissions.has($permission) => {
+                            Outcome::Success(Self(auth))
+                        }
+                        Outcome::Success(_) => Outcome::Failure((Status::Forbidden, ())),
+                        Outcome::Failure(failure) => Outcome::Failure(failure),
+                        Outcome::Forward(forward) => Outcome::Forward(forward),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+permission_guard! {
+    Admin => permissions::ADMIN,
+    ViewPending => permissions::VIEW_PENDING,
+    ViewReceived => permissions::VIEW_RECEIVED,
+    EditPending => permissions::EDIT_PENDING,
+    CreateOrders => permissions::CREATE_ORDERS,
+    EditReceived => permissions::EDIT_RECEIVED,
+    RemoveOrders => permissions::REMOVE_ORDERS,
+    EditProducts => permissions::EDIT_PRODUCTS,
+    ViewProducts => permissions::VIEW_PRODUCTS,
+    ViewSuppliers => permissions::VIEW_SUPPLIERS,
+}
+
+async fn from_api_key(conn: &mut AsyncPgConnection, key: &str) -> QueryResult<Option<AuthGuard>> {
+    let api_key = match ApiKey::from_key(conn, key).await? {
+        Some(api_key) => api_key,
+        None => return Ok(None),
+    };
+    let user = User::from_id(conn, api_key.created_by).await?;
+    let permissions = user
+        .get_permissions(conn)
+        .await?
+        .restrict_to(&api_key.scopes());
+
+    Ok(Some(AuthGuard {
+        user_id: user.id,
+        permissions,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct AccessToken {
+    pub access_token: String,
+    pub expires: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct LoginResponse {
+    pub access_token: String,
+    pub expires: NaiveDateTime,
+    pub refresh_token: String,
+    pub refresh_expires: NaiveDateTime,
+}
+
+#[post("/login", data = "<credentials>")]
+pub async fn login(
+    state: &State<ServerState>,
+    credentials: Json<Credentials>,
+) -> ApiResult<Json<LoginResponse>> {
+    use crate::schema::users::dsl::*;
+
+    let credentials = credentials.into_inner();
+    let mut conn = state.db_pool.get().await?;
+
+    let user = users
+        .filter(name.eq(&credentials.username))
+        .first::<User>(conn.as_mut())
+        .await
+        .optional()?
+        .ok_or(ApiError::Unauthorized)?;
+    let password_match = verify(
+        credentials.password + &env::var("PEPPER").expect("PEPPER must be set"),
+        &user.password,
+    )?;
+
+    if !password_match {
+        return Err(ApiError::Unauthorized);
+    }
+
+    Session::delete_expired(conn.as_mut(), user.id).await?;
+    let (refresh_token, session) =
+        Session::create(conn.as_mut(), user.id, Duration::hours(SESSION_LENGTH_HOURS)).await?;
+    let permissions = user.get_permissions(conn.as_mut()).await?;
+    let (access_token, expires) = issue_access_token(&state.token_keys, user.id, permissions)?;
+
+    Ok(Json(LoginResponse {
+        access_token,
+        expires,
+        refresh_token,
+        refresh_expires: session.expires,
+    }))
+}
+
+/// Trades a refresh token for a new access token. Permissions are reloaded, so
+/// changes made by an admin take effect at the next refresh.
+#[post("/refresh", data = "<request>")]
+pub async fn refresh(
+    state: &State<ServerState>,
+    request: Json<RefreshRequest>,
+) -> ApiResult<Json<AccessToken>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let session = Session::from_token(conn.as_mut(), &request.refresh_token)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    let user = User::from_id(conn.as_mut(), session.user_id).await?;
+    let permissions = user.get_permissions(conn.as_mut()).await?;
+    let (access_token, expires) = issue_access_token(&state.token_keys, user.id, permissions)?;
+
+    Ok(Json(AccessToken {
+        access_token,
+        expires,
+    }))
+}
+
+#[post("/logout", data = "<request>")]
+pub async fn logout(
+    auth: AuthGuard,
+    state: &State<ServerState>,
+    request: Json<RefreshRequest>,
+) -> ApiResult<()> {
+    let mut conn = state.db_pool.get().await?;
+
+    if let Some(session) = Session::from_token(conn.as_mut(), &request.refresh_token).await? {
+        if session.user_id == auth.user_id {
+            Session::delete(conn.as_mut(), &session.token_hash).await?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[post("/api_keys", data = "<request>")]
+pub async fn new_api_key(
+    admin: Admin,
+    state: &State<ServerState>,
+    request: Json<NewApiKeyRequest>,
+) -> ApiResult<Json<CreatedApiKey>> {
+    let request = request.into_inner();
+    if let Some(scope) = request
+        .scopes
+        .iter()
+        .find(|scope| !permissions::is_known(scope))
+    {
+        return Err(ApiError::Validation(format!("Unknown scope {}", scope)));
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let (key, api_key) =
+                ApiKey::create(conn, request.name, admin.0.user_id, request.scopes).await?;
+
+            Ok(Json(CreatedApiKey { key, api_key }))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[get("/api_keys")]
+pub async fn api_keys(
+    _auth: Admin,
+    state: &State<ServerState>,
+) -> ApiResult<Json<Vec<ApiKey>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(ApiKey::get_all(conn.as_mut()).await?))
+}
+
+#[delete("/api_keys/<id>")]
+pub async fn revoke_api_key(auth: Admin, state: &State<ServerState>, id: i32) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            ApiKey::revoke(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
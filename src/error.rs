@@ -0,0 +1,233 @@
+use crate::database::PoolError;
+use deadpool::managed;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::Request;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use validator::ValidationErrors;
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(String),
+    Unauthorized,
+    Forbidden,
+    Validation(String),
+    /// Messages for each request field that failed validation
+    InvalidFields(BTreeMap<String, Vec<String>>),
+    Conflict(String),
+    DatabaseUnavailable,
+    Internal(String),
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody {
+    error: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::Unauthorized => Status::Unauthorized,
+            ApiError::Forbidden => Status::Forbidden,
+            ApiError::Validation(_) | ApiError::InvalidFields(_) => Status::UnprocessableEntity,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::DatabaseUnavailable => Status::ServiceUnavailable,
+            ApiError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden => "forbidden",
+            ApiError::Validation(_) | ApiError::InvalidFields(_) => "validation",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::DatabaseUnavailable => "database_unavailable",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::Validation(message)
+            | ApiError::Conflict(message) => message.clone(),
+            ApiError::InvalidFields(_) => String::from("Some fields are invalid"),
+            ApiError::Unauthorized => String::from("Missing or invalid credentials"),
+            ApiError::Forbidden => String::from("Missing the permission required for this action"),
+            ApiError::DatabaseUnavailable => String::from("The database is unavailable"),
+            // Internal details are logged, not sent to the client
+            ApiError::Internal(_) => String::from("Internal server error"),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        if let ApiError::Internal(details) = &self {
+            eprintln!("{} {}: {}", req.method(), req.uri(), details);
+        }
+        let status = self.status();
+        let body = ErrorBody {
+            error: self.code(),
+            message: self.message(),
+            fields: match self {
+                ApiError::InvalidFields(fields) => Some(fields),
+                _ => None,
+            },
+        };
+        Response::build_from(Json(body).respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+/// The error for a request that broke a constraint the database enforces, such
+/// as a unique UPC or a non-negative price.
+fn violation(constraint: &str) -> Option<ApiError> {
+    let conflict = |message: &str| Some(ApiError::Conflict(message.to_string()));
+    let invalid = |field: &str, message: &str| {
+        Some(ApiError::InvalidFields(BTreeMap::from([(
+            field.to_string(),
+            vec![message.to_string()],
+        )])))
+    };
+    let negative = "must not be negative";
+    let missing = |field: &str, entity: &str| {
+        invalid(field, &format!("must refer to an existing {}", entity))
+    };
+    match constraint {
+        "users_name_key" => conflict("A user with this name already exists"),
+        "users_email_key" => conflict("A user with this email already exists"),
+        "roles_name_key" => conflict("A role with this name already exists"),
+        "products_upc_key" => conflict("A product with this UPC already exists"),
+        "products_amount_check" => invalid("amount", "stock must not go below zero"),
+        "products_case_size_check" => invalid("case_size", "must be at least 1"),
+        "products_cost_price_per_unit_check" => invalid("cost_price_per_unit", negative),
+        "products_selling_price_per_unit_check" => invalid("selling_price_per_unit", negative),
+        "products_sale_price_check" => invalid("sale_price", negative),
+        "products_buy_level_check" => invalid("buy_level", negative),
+        "pending_orders_amount_check" => invalid("amount", negative),
+        "received_orders_gross_amount_check" => invalid("gross_amount", negative),
+        "received_orders_actually_received_check" => invalid("actually_received", negative),
+        "received_orders_damaged_check" => invalid(
+            "damaged",
+            "must not be negative or exceed actually_received",
+        ),
+        "pending_orders_product_id_fkey"
+        | "received_orders_product_id_fkey"
+        | "product_categories_product_id_fkey"
+        | "product_suppliers_product_id_fkey"
+        | "stock_movements_product_id_fkey" => missing("product_id", "product"),
+        "products_brand_id_fkey" => missing("brand_id", "brand"),
+        "product_categories_category_id_fkey" => missing("category_id", "category"),
+        "product_suppliers_supplier_id_fkey" => missing("supplier_id", "supplier"),
+        "user_roles_role_id_fkey" => missing("role_id", "role"),
+        _ => None,
+    }
+}
+
+impl From<DieselError> for ApiError {
+    fn from(error: DieselError) -> Self {
+        if let DieselError::DatabaseError(_, info) = &error {
+            if let Some(error) = info.constraint_name().and_then(violation) {
+                return error;
+            }
+        }
+        match error {
+            DieselError::NotFound => ApiError::NotFound(String::from("Record not found")),
+            // The database's own messages name tables and internals, so they aren't passed on
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                ApiError::Conflict(String::from("A record with these values already exists"))
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                ApiError::Conflict(String::from(
+                    "The change refers to a record that doesn't exist or is still in use",
+                ))
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::CheckViolation, _) => {
+                ApiError::Validation(String::from("A value is out of the allowed range"))
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ClosedConnection, _)
+            | DieselError::DatabaseError(DatabaseErrorKind::UnableToSendCommand, _) => {
+                ApiError::DatabaseUnavailable
+            }
+            // Values bound into a query come from the request, such as a cursor's sort key
+            DieselError::SerializationError(error) => ApiError::Validation(error.to_string()),
+            error => ApiError::Internal(error.to_string()),
+        }
+    }
+}
+
+impl From<managed::PoolError<PoolError>> for ApiError {
+    fn from(_: managed::PoolError<PoolError>) -> Self {
+        ApiError::DatabaseUnavailable
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(error: serde_json::Error) -> Self {
+        ApiError::Validation(error.to_string())
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut fields = BTreeMap::new();
+        for (field, errors) in errors.field_errors() {
+            for error in errors {
+                // Struct level errors name the field they're about
+                let field = error
+                    .params
+                    .get("field")
+                    .and_then(|field| field.as_str())
+                    .unwrap_or(field);
+                let message = error.message.as_ref().unwrap_or(&error.code);
+                fields
+                    .entry(field.to_string())
+                    .or_insert_with(Vec::new)
+                    .push(message.to_string());
+            }
+        }
+        ApiError::InvalidFields(fields)
+    }
+}
+
+impl From<bcrypt::BcryptError> for ApiError {
+    fn from(error: bcrypt::BcryptError) -> Self {
+        ApiError::Internal(error.to_string())
+    }
+}
+
+/// Keeps error bodies JSON for failures Rocket handles itself, such as failed
+/// guards or a request body that doesn't deserialize.
+#[catch(default)]
+pub fn default_catcher(status: Status, _req: &Request) -> (Status, Json<ErrorBody>) {
+    let error = match status.code {
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        409 => "conflict",
+        400 | 422 => "validation",
+        503 => "database_unavailable",
+        _ => "internal",
+    };
+    (
+        status,
+        Json(ErrorBody {
+            error,
+            message: status.reason().unwrap_or("Unknown error").to_string(),
+            fields: None,
+        }),
+    )
+}
@@ -0,0 +1,67 @@
+use crate::error::{ApiError, ApiResult};
+use chrono::NaiveDateTime;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use std::convert::Infallible;
+
+/// The entity tag for a row last updated at `updated_at`.
+pub fn etag(updated_at: NaiveDateTime) -> String {
+    format!(
+        "\"{}.{:06}\"",
+        updated_at.timestamp(),
+        updated_at.timestamp_subsec_micros()
+    )
+}
+
+fn parse(tag: &str) -> Option<NaiveDateTime> {
+    let (seconds, micros) = tag
+        .trim()
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .split_once('.')?;
+    let nanos = micros.parse::<u32>().ok()?.checked_mul(1000)?;
+    NaiveDateTime::from_timestamp_opt(seconds.parse().ok()?, nanos)
+}
+
+/// Responds with `R`, adding an `ETag` header for the given version.
+pub struct Tagged<R>(pub R, pub NaiveDateTime);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Tagged<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        Response::build_from(self.0.respond_to(req)?)
+            .raw_header("ETag", etag(self.1))
+            .ok()
+    }
+}
+
+/// The `If-Match` header of a request, if it sent one.
+pub struct IfMatch(Option<String>);
+
+impl IfMatch {
+    /// The version the client expects to be replacing. `None` if the header is
+    /// missing or `*`.
+    pub fn version(&self) -> ApiResult<Option<NaiveDateTime>> {
+        match self.0.as_deref().map(str::trim) {
+            None | Some("*") => Ok(None),
+            Some(tag) => parse(tag).map(Some).ok_or_else(stale),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfMatch(
+            req.headers().get_one("If-Match").map(str::to_string),
+        ))
+    }
+}
+
+/// The error for a write based on a version that is no longer current.
+pub fn stale() -> ApiError {
+    ApiError::Conflict(String::from(
+        "Modified by someone else since it was last read",
+    ))
+}
@@ -0,0 +1,228 @@
+use crate::models::{AuditAction, AuditEntry};
+use crate::permissions::{self, PermissionSet};
+use chrono::NaiveDateTime;
+use diesel::QueryResult;
+use diesel_async::AsyncPgConnection;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+/// How often subscribers check the audit log for new changes. Each check
+/// sends at most `BATCH_SIZE` of them.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often subscribers' credentials and permissions are checked again.
+#[cfg(not(test))]
+pub const AUTH_INTERVAL: Duration = Duration::from_secs(60);
+// Short enough for tests to see a revoked credential end the stream
+#[cfg(test)]
+pub const AUTH_INTERVAL: Duration = Duration::from_millis(200);
+/// How long a missing audit id is waited for at most, while transactions older
+/// than it keep running.
+const GAP_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 500;
+
+/// The permission needed to see changes to `entity`. Users, roles and API keys
+/// are never published.
+pub fn permission(entity: &str) -> Option<&'static str> {
+    match entity {
+        "product" | "product_category" | "product_supplier" | "brand" | "category" => {
+            Some(permissions::VIEW_PRODUCTS)
+        }
+        "supplier" => Some(permissions::VIEW_SUPPLIERS),
+        "pending_order" => Some(permissions::VIEW_PENDING),
+        "received_order" => Some(permissions::VIEW_RECEIVED),
+        _ => None,
+    }
+}
+
+/// Whether `permissions` let a subscriber see any change at all.
+pub fn can_subscribe(permissions: &PermissionSet) -> bool {
+    [
+        permissions::VIEW_PRODUCTS,
+        permissions::VIEW_SUPPLIERS,
+        permissions::VIEW_PENDING,
+        permissions::VIEW_RECEIVED,
+    ]
+    .iter()
+    .any(|permission| permissions.has(permission))
+}
+
+/// A change as sent to subscribers. `data` is the record after the change and
+/// is `null` for deletes.
+#[derive(Debug, Serialize)]
+pub struct ChangeEvent {
+    pub id: i32,
+    pub entity: String,
+    pub entity_id: i32,
+    pub action: AuditAction,
+    pub occurred_at: NaiveDateTime,
+    pub data: Option<serde_json::Value>,
+}
+
+impl ChangeEvent {
+    /// The SSE event type, such as `product.update`.
+    pub fn kind(&self) -> String {
+        let action = match self.action {
+            AuditAction::Insert => "insert",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+        };
+        format!("{}.{}", self.entity, action)
+    }
+}
+
+impl From<AuditEntry> for ChangeEvent {
+    fn from(entry: AuditEntry) -> Self {
+        ChangeEvent {
+            id: entry.id,
+            entity: entry.entity,
+            entity_id: entry.entity_id,
+            action: entry.action,
+            occurred_at: entry.occurred_at,
+            data: entry.after,
+        }
+    }
+}
+
+/// A subscriber's position in the audit log.
+///
+/// Audit ids are handed out when a change is made but only become visible when
+/// its transaction commits, so a later id can show up before an earlier one.
+/// The feed stops at a missing id until every transaction that could still
+/// commit it has finished, or `GAP_TIMEOUT` passes, so no committed change is
+/// skipped.
+pub struct Feed {
+    last: i32,
+    gap: Option<Gap>,
+}
+
+/// Missing ids up to `until`, the newest visible when they were found. They
+/// belong to transactions that started before `horizon`.
+struct Gap {
+    until: i32,
+    horizon: i64,
+    since: Instant,
+}
+
+impl Feed {
+    /// Starts after `last_event_id`, or at the newest change when not resuming.
+    pub async fn start(
+        conn: &mut AsyncPgConnection,
+        last_event_id: Option<i32>,
+    ) -> QueryResult<Self> {
+        let last = match last_event_id {
+            Some(id) => id,
+            None => AuditEntry::last_id(conn).await?,
+        };
+        Ok(Feed { last, gap: None })
+    }
+
+    /// The changes committed since the last call, oldest first.
+    pub async fn next(&mut self, conn: &mut AsyncPgConnection) -> QueryResult<Vec<ChangeEvent>> {
+        let entries = AuditEntry::after(conn, self.last, BATCH_SIZE).await?;
+        let newest = entries.last().map_or(self.last, |entry| entry.id);
+        let mut events = Vec::new();
+        for entry in entries {
+            if entry.id != self.last + 1 && !self.skip_gap(conn, entry.id, newest).await? {
+                break;
+            }
+            self.last = entry.id;
+            events.push(ChangeEvent::from(entry));
+        }
+        Ok(events)
+    }
+
+    /// Whether the ids missing before `next` can't show up anymore.
+    async fn skip_gap(
+        &mut self,
+        conn: &mut AsyncPgConnection,
+        next: i32,
+        newest: i32,
+    ) -> QueryResult<bool> {
+        let (oldest_running, first_unstarted) = AuditEntry::transaction_horizon(conn).await?;
+        let gap = match &self.gap {
+            Some(gap) if next <= gap.until => gap,
+            _ => self.gap.insert(Gap {
+                until: newest,
+                horizon: first_unstarted,
+                since: Instant::now(),
+            }),
+        };
+        Ok(oldest_running >= gap.horizon || gap.since.elapsed() >= GAP_TIMEOUT)
+    }
+}
+
+/// The `Last-Event-ID` header an `EventSource` sends when it reconnects.
+pub struct LastEventId(pub Option<i32>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(LastEventId(
+            req.headers()
+                .get_one("Last-Event-ID")
+                .and_then(|id| id.trim().parse().ok()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Manager, Pool};
+    use crate::error::ApiError;
+    use crate::models::tests::test_actor;
+    use crate::models::{generate_token, BrandBuilder};
+    use crate::services;
+    use diesel_async::scoped_futures::ScopedFutureExt;
+
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn rolled_back_changes_are_skipped() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let actor = test_actor(conn.as_mut()).await;
+        let mut feed = Feed::start(conn.as_mut(), None).await.unwrap();
+        drop(conn);
+
+        let rolled_back = generate_token();
+        let result = services::transaction(&pool, |conn| {
+            let name = rolled_back.clone();
+            async move {
+                BrandBuilder::new(name).build(conn, actor.id).await?;
+                Err::<(), _>(ApiError::Validation(String::from("rolled back")))
+            }
+            .scope_boxed()
+        })
+        .await;
+        assert!(result.is_err());
+        let mut conn = pool.get().await.unwrap();
+        let committed = generate_token();
+        let brand = BrandBuilder::new(committed.clone())
+            .build(conn.as_mut(), actor.id)
+            .await
+            .unwrap();
+
+        // Other tests' transactions may hold the gap open until `GAP_TIMEOUT`
+        let started = Instant::now();
+        let mut names = Vec::new();
+        while !names.contains(&committed) {
+            assert!(
+                started.elapsed() < GAP_TIMEOUT * 2,
+                "the gap was never skipped"
+            );
+            for event in feed.next(conn.as_mut()).await.unwrap() {
+                if event.entity == "brand" && event.action == AuditAction::Insert {
+                    let name = event.data.unwrap()["name"].as_str().unwrap().to_string();
+                    assert_eq!(event.entity_id == brand, name == committed);
+                    names.push(name);
+                }
+            }
+            rocket::tokio::time::sleep(POLL_INTERVAL / 10).await;
+        }
+        assert!(!names.contains(&rolled_back));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::database::Pool;
+use crate::error::{ApiError, ApiResult};
+use crate::models::IdempotentRequest;
+use crate::services;
+use chrono::Duration;
+use diesel::result::Error as DieselError;
+use diesel_async::scoped_futures::{ScopedBoxFuture, ScopedFutureExt};
+use diesel_async::AsyncPgConnection;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+
+/// How long a key is remembered. A retry after that runs as a new request.
+const WINDOW_HOURS: i64 = 24;
+const MAX_KEY_LENGTH: usize = 255;
+
+/// The `Idempotency-Key` header of a request, if it sent one, and the method,
+/// path and body it was sent with.
+pub struct IdempotencyKey {
+    key: Option<String>,
+    request: String,
+    body: Vec<u8>,
+}
+
+impl IdempotencyKey {
+    /// Ties the key to the request's body as well, so that it can't be replayed
+    /// for a different one.
+    pub fn with_body<B: Serialize + ?Sized>(self, body: &B) -> ApiResult<Self> {
+        let body = serde_json::to_vec(body)
+            .map_err(|error| DieselError::SerializationError(Box::new(error)))?;
+        Ok(IdempotencyKey { body, ..self })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IdempotencyKey {
+            key: req
+                .headers()
+                .get_one("Idempotency-Key")
+                .map(|key| key.trim().to_string()),
+            request: format!("{} {}", req.method(), req.uri().path()),
+            body: Vec::new(),
+        })
+    }
+}
+
+/// Runs `operation` like `services::transaction`. Given a key, it runs at most
+/// once per user and key: retries get the first response back instead, as
+/// long as it succeeded. Failed requests aren't remembered and can be retried.
+/// Reusing a key with a different body is an error rather than a replay.
+pub async fn transaction<'a, T, F>(
+    pool: &Pool,
+    key: &'a IdempotencyKey,
+    user_id: i32,
+    operation: F,
+) -> ApiResult<T>
+where
+    F: for<'r> FnOnce(&'r mut AsyncPgConnection) -> ScopedBoxFuture<'a, 'r, ApiResult<T>>
+        + Send
+        + 'a,
+    T: Serialize + DeserializeOwned + Send + 'a,
+{
+    let idempotency_key = match &key.key {
+        Some(idempotency_key) => idempotency_key,
+        None => return services::transaction(pool, operation).await,
+    };
+    if idempotency_key.is_empty() || idempotency_key.len() > MAX_KEY_LENGTH {
+        return Err(ApiError::Validation(format!(
+            "Idempotency-Key must be 1 to {} characters",
+            MAX_KEY_LENGTH
+        )));
+    }
+    let to_error = |error: serde_json::Error| DieselError::SerializationError(Box::new(error));
+    let fingerprint = format!("{} {:x}", key.request, Sha256::digest(&key.body));
+    services::transaction(pool, |conn| {
+        async move {
+            let window = Duration::hours(WINDOW_HOURS);
+            let earlier =
+                IdempotentRequest::begin(conn, user_id, idempotency_key, &fingerprint, window)
+                    .await?;
+            if let Some(earlier) = earlier {
+                if earlier.request != fingerprint {
+                    // Everything but the body hash
+                    let (used_for, _) = earlier.request.rsplit_once(' ').unwrap_or_default();
+                    if used_for == key.request {
+                        return Err(ApiError::Validation(String::from(
+                            "Idempotency-Key was already used with a different request body",
+                        )));
+                    }
+                    return Err(ApiError::Validation(format!(
+                        "Idempotency-Key was already used for {}",
+                        used_for
+                    )));
+                }
+                // Only unset while the first request's transaction is running
+                let response = earlier.response.unwrap_or_default();
+                return Ok(serde_json::from_value(response).map_err(to_error)?);
+            }
+            let response = operation(conn).await?;
+            let stored = serde_json::to_value(&response).map_err(to_error)?;
+            IdempotentRequest::complete(conn, user_id, idempotency_key, &stored).await?;
+            Ok(response)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Manager;
+    use crate::models::generate_token;
+    use crate::models::tests::test_actor;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn key(key: &str, body: &serde_json::Value) -> IdempotencyKey {
+        IdempotencyKey {
+            key: Some(key.to_string()),
+            request: String::from("POST /products"),
+            body: Vec::new(),
+        }
+        .with_body(body)
+        .unwrap()
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn a_reused_key_replays_the_response_only_for_the_same_body() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let actor = test_actor(pool.get().await.unwrap().as_mut()).await;
+        let token = generate_token();
+        let runs = AtomicU32::new(0);
+        let (pool, runs) = (&pool, &runs);
+        let run = |key: IdempotencyKey| async move {
+            transaction(pool, &key, actor.id, |_| {
+                async move { Ok(runs.fetch_add(1, Ordering::SeqCst)) }.scope_boxed()
+            })
+            .await
+        };
+
+        let body = serde_json::json!({ "name": "Granola" });
+        assert_eq!(run(key(&token, &body)).await.unwrap(), 0);
+        assert_eq!(run(key(&token, &body)).await.unwrap(), 0);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        let other = serde_json::json!({ "name": "Muesli" });
+        match run(key(&token, &other)).await {
+            Err(ApiError::Validation(message)) => assert_eq!(
+                message,
+                "Idempotency-Key was already used with a different request body"
+            ),
+            _ => panic!("a different body must not be replayed"),
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,32 +1,208 @@
+use crate::pagination::Cursor;
+use crate::permissions::PermissionSet;
 use crate::schema::*;
+use crate::validation;
 use bcrypt::hash;
 use bcrypt::verify;
 use bigdecimal::BigDecimal;
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel::associations::*;
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::Insertable;
 use diesel::QueryDsl;
 use diesel::Queryable;
 use diesel_async::AsyncPgConnection;
 use diesel_async::RunQueryDsl;
+use diesel_derive_enum::DbEnum;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use validator::{Validate, ValidationError};
 
-const PEPPER: &str = "TUbqRXu96kfVDf";
 const DEFAULT_COST: usize = 10;
 
+/// Appended to passwords before hashing. Read from the environment, as
+/// `login` does, so that hashes made here verify there.
+fn pepper() -> String {
+    env::var("PEPPER").expect("PEPPER must be set")
+}
+
+/// What products are searched by. `products_search_idx` indexes exactly this
+/// expression, so the two have to stay the same.
+const PRODUCT_DOCUMENT: &str = "(setweight(to_tsvector('simple', upc), 'A') || \
+    setweight(to_tsvector('english', name), 'A') || \
+    setweight(to_tsvector('english', description), 'B'))";
+
+sql_function!(fn word_similarity(
+    text: diesel::sql_types::Text,
+    name: diesel::sql_types::Text,
+) -> diesel::sql_types::Float4);
+/// The least `word_similarity` a name needs to be suggested. Lower than
+/// pg_trgm's default of 0.6, which misses a single typo in a short name, such
+/// as "grnola" for "Granola".
+const WORD_SIMILARITY: f32 = 0.5;
+
+/// Deserializes a field that is present, even as `null`, to `Some`, so a
+/// changeset can tell clearing a column apart from leaving it alone.
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// A `LIKE` pattern matching values that contain `text`, with the wildcards
+/// `%` and `_` in it matched literally.
+fn contains_pattern(text: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Orders a boxed query on `$table` by `$column`, breaking ties by id, and
+/// starts it after the row a cursor points at. `$key` is the column's type.
+macro_rules! sort_by {
+    ($query:expr, $direction:expr, $after:expr, $table:ident::$column:ident: $key:ty) => {{
+        let mut query = $query;
+        if let Some(after) = $after {
+            let key: $key = after.key()?;
+            let tie = $table::$column.eq(key.clone()).and($table::id.gt(after.id));
+            query = match $direction {
+                SortDirection::Asc => query.filter($table::$column.gt(key).or(tie)),
+                SortDirection::Desc => query.filter($table::$column.lt(key).or(tie)),
+            };
+        }
+        match $direction {
+            SortDirection::Asc => query.order($table::$column.asc()).then_order_by($table::id),
+            SortDirection::Desc => query
+                .order($table::$column.desc())
+                .then_order_by($table::id),
+        }
+    }};
+}
+
+/// `sort_by!` for a nullable column. Postgres sorts nulls last when ascending
+/// and first when descending.
+macro_rules! sort_by_nullable {
+    ($query:expr, $direction:expr, $after:expr, $table:ident::$column:ident: $key:ty) => {{
+        let mut query = $query;
+        if let Some(after) = $after {
+            let key: Option<$key> = after.key()?;
+            let tie = $table::id.gt(after.id);
+            query = match (key, $direction) {
+                (Some(key), SortDirection::Asc) => query.filter(
+                    $table::$column
+                        .gt(key.clone())
+                        .or($table::$column.eq(key).and(tie))
+                        .or($table::$column.is_null()),
+                ),
+                (Some(key), SortDirection::Desc) => query.filter(
+                    $table::$column
+                        .lt(key.clone())
+                        .or($table::$column.eq(key).and(tie)),
+                ),
+                (None, SortDirection::Asc) => query.filter($table::$column.is_null().and(tie)),
+                (None, SortDirection::Desc) => query.filter($table::$column.is_not_null().or(tie)),
+            };
+        }
+        match $direction {
+            SortDirection::Asc => query.order($table::$column.asc()).then_order_by($table::id),
+            SortDirection::Desc => query
+                .order($table::$column.desc())
+                .then_order_by($table::id),
+        }
+    }};
+}
+
 #[derive(Queryable, Insertable, PartialEq, Eq, Debug, Identifiable, Deserialize, Serialize)]
 #[diesel(table_name = users)]
 pub struct User {
     pub id: i32,
     pub name: String,
     pub email: String,
+    #[serde(skip_serializing)]
     pub password: String,
+    pub updated_at: NaiveDateTime,
 }
 
-#[derive(Queryable, PartialEq, Debug, Insertable, Identifiable, Deserialize, Serialize)]
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+pub struct NewUserRow {
+    pub name: String,
+    pub email: String,
+    pub password: String,
+}
+
+/// A user as sent to PUT, which is what GET returns. Passwords can only be
+/// changed by patching, so they get hashed.
+#[derive(Deserialize)]
+pub struct UserUpdate {
+    #[serde(default)]
+    pub id: i32,
+    pub name: String,
+    pub email: String,
+    pub updated_at: NaiveDateTime,
+}
+
+/// The fields of a user to change. `password` must already be hashed.
+#[derive(AsChangeset, Deserialize)]
+#[diesel(table_name = users)]
+#[serde(deny_unknown_fields)]
+pub struct UserChanges {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(
+    Queryable,
+    QueryableByName,
+    PartialEq,
+    Debug,
+    Insertable,
+    Identifiable,
+    Deserialize,
+    Serialize,
+    Validate,
+)]
+#[diesel(table_name = products)]
 pub struct Product {
     pub id: i32,
+    #[validate(custom = "validation::upc")]
+    pub upc: String,
+    #[validate(custom = "validation::not_blank")]
+    pub name: String,
+    pub description: String,
+    pub amount: f64,
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub case_size: Option<i32>,
+    pub measure_by_weight: bool,
+    #[validate(custom = "validation::price")]
+    pub cost_price_per_unit: BigDecimal,
+    #[validate(custom = "validation::price")]
+    pub selling_price_per_unit: BigDecimal,
+    pub sale_end: Option<NaiveDateTime>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub buy_level: Option<f64>,
+    #[validate(custom = "validation::price")]
+    pub sale_price: Option<BigDecimal>,
+    pub brand_id: Option<i32>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = products)]
+pub struct NewProductRow {
     pub upc: String,
     pub name: String,
     pub description: String,
@@ -38,6 +214,78 @@ pub struct Product {
     pub sale_end: Option<NaiveDateTime>,
     pub buy_level: Option<f64>,
     pub sale_price: Option<BigDecimal>,
+    pub brand_id: Option<i32>,
+}
+
+/// The fields of a product to change. Stock goes through the stock ledger, so
+/// `amount` can't be patched.
+#[derive(AsChangeset, Deserialize, Validate)]
+#[diesel(table_name = products)]
+#[serde(deny_unknown_fields)]
+pub struct ProductChanges {
+    #[validate(custom = "validation::upc")]
+    pub upc: Option<String>,
+    #[validate(custom = "validation::not_blank")]
+    pub name: Option<String>,
+    pub description: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub case_size: Option<Option<i32>>,
+    pub measure_by_weight: Option<bool>,
+    #[validate(custom = "validation::price")]
+    pub cost_price_per_unit: Option<BigDecimal>,
+    #[validate(custom = "validation::price")]
+    pub selling_price_per_unit: Option<BigDecimal>,
+    #[serde(default, deserialize_with = "present")]
+    pub sale_end: Option<Option<NaiveDateTime>>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub buy_level: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(custom = "validation::price")]
+    pub sale_price: Option<Option<BigDecimal>>,
+    #[serde(default, deserialize_with = "present")]
+    pub brand_id: Option<Option<i32>>,
+}
+
+/// A product found by full-text search. The highlights wrap the words that
+/// matched in `<mark>` tags.
+#[derive(QueryableByName, PartialEq, Debug, Serialize)]
+pub struct ProductMatch {
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub product: Product,
+    #[diesel(sql_type = diesel::sql_types::Float4)]
+    pub rank: f32,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub name_highlight: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub description_highlight: String,
+}
+
+impl From<Similar<Product>> for ProductMatch {
+    fn from(similar: Similar<Product>) -> Self {
+        ProductMatch {
+            rank: similar.similarity,
+            name_highlight: similar.item.name.clone(),
+            description_highlight: similar.item.description.clone(),
+            product: similar.item,
+        }
+    }
+}
+
+/// A row found by trigram similarity, and how similar it was from 0 to 1.
+#[derive(PartialEq, Debug, Serialize)]
+pub struct Similar<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub similarity: f32,
+}
+
+impl<T> From<(T, f32)> for Similar<T> {
+    fn from((item, similarity): (T, f32)) -> Self {
+        Similar { item, similarity }
+    }
 }
 
 #[derive(Queryable, PartialEq, Eq, Debug, Insertable, Associations, Deserialize, Serialize)]
@@ -47,51 +295,149 @@ pub struct Preference {
     pub user_id: i32,
 }
 
-#[derive(Queryable, PartialEq, Eq, Debug, Insertable, Associations, Deserialize, Serialize)]
-#[diesel(belongs_to(User))]
-#[diesel(table_name = permissions)]
-pub struct Permission {
-    pub user_id: i32,
-    pub admin: bool,
-    pub view_pending: bool,
-    pub view_received: bool,
-    pub edit_pending: bool,
-    pub create_orders: bool,
-    pub edit_received: bool,
-    pub remove_orders: bool,
-    pub edit_products: bool,
-    pub view_products: bool,
-    pub view_suppliers: bool,
-}
-
-#[derive(Queryable, PartialEq, Eq, Debug, Insertable, Deserialize, Serialize)]
+#[derive(Queryable, PartialEq, Eq, Debug, Identifiable, Deserialize, Serialize)]
+#[diesel(table_name = roles)]
+pub struct Role {
+    pub id: i32,
+    pub name: String,
+}
+
+/// A product with whichever related records were asked for. Those left out
+/// are left out of the JSON too, while an included product without a brand
+/// has `"brand": null`.
+#[derive(PartialEq, Debug, Serialize)]
+pub struct ProductWithRelations {
+    #[serde(flatten)]
+    pub product: Product,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brand: Option<Option<Brand>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub categories: Option<Vec<Category>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suppliers: Option<Vec<Supplier>>,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize)]
+pub struct RoleWithPermissions {
+    #[serde(flatten)]
+    pub role: Role,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Queryable, PartialEq, Eq, Debug, Clone, Insertable, Deserialize, Serialize, Validate)]
 #[diesel(table_name = categories)]
 pub struct Category {
     pub id: i32,
-    pub products: Vec<Option<i32>>,
+    #[validate(custom = "validation::not_blank")]
+    pub name: String,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = categories)]
+pub struct NewCategoryRow {
     pub name: String,
 }
 
-#[derive(Queryable, PartialEq, Eq, Debug, Insertable, Deserialize, Serialize)]
+#[derive(AsChangeset, Deserialize, Validate)]
+#[diesel(table_name = categories)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryChanges {
+    #[validate(custom = "validation::not_blank")]
+    pub name: Option<String>,
+}
+
+#[derive(Queryable, PartialEq, Eq, Debug, Clone, Insertable, Deserialize, Serialize, Validate)]
 pub struct Supplier {
     pub id: i32,
-    pub products: Vec<Option<i32>>,
+    #[validate(custom = "validation::not_blank")]
+    pub name: String,
+    #[validate(custom = "validation::phone_number")]
+    pub phone_number: Option<String>,
+    #[validate(email(message = "must be an email address"))]
+    pub email: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = suppliers)]
+pub struct NewSupplierRow {
     pub name: String,
     pub phone_number: Option<String>,
     pub email: Option<String>,
 }
 
-#[derive(Queryable, PartialEq, Eq, Debug, Insertable, Deserialize, Serialize)]
+#[derive(AsChangeset, Deserialize, Validate)]
+#[diesel(table_name = suppliers)]
+#[serde(deny_unknown_fields)]
+pub struct SupplierChanges {
+    #[validate(custom = "validation::not_blank")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(custom = "validation::phone_number")]
+    pub phone_number: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    #[validate(email(message = "must be an email address"))]
+    pub email: Option<Option<String>>,
+}
+
+#[derive(Queryable, PartialEq, Eq, Debug, Clone, Insertable, Deserialize, Serialize, Validate)]
 pub struct Brand {
     pub id: i32,
+    #[validate(custom = "validation::not_blank")]
+    pub name: String,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = brands)]
+pub struct NewBrandRow {
     pub name: String,
-    pub products: Vec<Option<i32>>,
 }
 
-#[derive(Queryable, PartialEq, Debug, Insertable, Associations, Deserialize, Serialize, Clone)]
+#[derive(AsChangeset, Deserialize, Validate)]
+#[diesel(table_name = brands)]
+#[serde(deny_unknown_fields)]
+pub struct BrandChanges {
+    #[validate(custom = "validation::not_blank")]
+    pub name: Option<String>,
+}
+
+#[derive(Queryable, PartialEq, Eq, Debug, Insertable, Serialize)]
+#[diesel(table_name = product_categories)]
+pub struct ProductCategory {
+    pub product_id: i32,
+    pub category_id: i32,
+}
+
+#[derive(Queryable, PartialEq, Eq, Debug, Insertable, Serialize)]
+#[diesel(table_name = product_suppliers)]
+pub struct ProductSupplier {
+    pub product_id: i32,
+    pub supplier_id: i32,
+}
+
+#[derive(
+    Queryable, PartialEq, Debug, Insertable, Associations, Deserialize, Serialize, Clone, Validate,
+)]
 #[diesel(belongs_to(Product))]
+#[validate(schema(function = "validate_received_order"))]
 pub struct ReceivedOrder {
     pub id: i32,
+    pub received: Option<NaiveDateTime>,
+    pub product_id: i32,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub gross_amount: f64,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub actually_received: f64,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub damaged: f64,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = received_orders)]
+pub struct NewReceivedOrderRow {
     pub received: Option<NaiveDateTime>,
     pub product_id: i32,
     pub gross_amount: f64,
@@ -99,19 +445,377 @@ pub struct ReceivedOrder {
     pub damaged: f64,
 }
 
-#[derive(Queryable, PartialEq, Debug, Insertable, Associations, Deserialize, Serialize)]
+#[derive(AsChangeset, Deserialize, Validate)]
+#[diesel(table_name = received_orders)]
+#[serde(deny_unknown_fields)]
+#[validate(schema(function = "validate_received_order_changes"))]
+pub struct ReceivedOrderChanges {
+    #[serde(default, deserialize_with = "present")]
+    pub received: Option<Option<NaiveDateTime>>,
+    pub product_id: Option<i32>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub gross_amount: Option<f64>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub actually_received: Option<f64>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub damaged: Option<f64>,
+}
+
+fn validate_received_order(order: &ReceivedOrder) -> Result<(), ValidationError> {
+    validation::received_amounts(order.actually_received, order.damaged)
+}
+
+/// Only checked when both amounts change. A patch of just one is checked against
+/// the stored order by the database.
+fn validate_received_order_changes(changes: &ReceivedOrderChanges) -> Result<(), ValidationError> {
+    match (changes.actually_received, changes.damaged) {
+        (Some(actually_received), Some(damaged)) => {
+            validation::received_amounts(actually_received, damaged)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(
+    Queryable, PartialEq, Debug, Insertable, Associations, Deserialize, Serialize, Validate,
+)]
 #[diesel(belongs_to(Product))]
 pub struct PendingOrder {
     pub id: i32,
+    pub product_id: i32,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub amount: f64,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = pending_orders)]
+pub struct NewPendingOrderRow {
     pub product_id: i32,
     pub amount: f64,
 }
 
+#[derive(AsChangeset, Deserialize, Validate)]
+#[diesel(table_name = pending_orders)]
+#[serde(deny_unknown_fields)]
+pub struct PendingOrderChanges {
+    pub product_id: Option<i32>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub amount: Option<f64>,
+}
+
+#[derive(Queryable, PartialEq, Eq, Debug, Identifiable, Serialize)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub prefix: String,
+    pub created_by: i32,
+    pub scopes: Vec<Option<String>>,
+    pub created: NaiveDateTime,
+    pub revoked: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_keys)]
+struct NewApiKey {
+    name: String,
+    key_hash: String,
+    prefix: String,
+    created_by: i32,
+    scopes: Vec<Option<String>>,
+}
+
+#[derive(Queryable, PartialEq, Eq, Debug, Identifiable, Serialize)]
+#[diesel(table_name = webhooks)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<Option<String>>,
+    pub created_by: Option<i32>,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhooks)]
+struct NewWebhook {
+    url: String,
+    secret: String,
+    events: Vec<Option<String>>,
+    created_by: Option<i32>,
+}
+
+/// One event sent, or still to be sent, to one webhook.
+#[derive(Queryable, QueryableByName, PartialEq, Debug, Identifiable, Serialize)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    /// When to try again. `None` once delivered or out of attempts.
+    pub next_attempt: Option<NaiveDateTime>,
+    pub delivered: Option<NaiveDateTime>,
+    pub last_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created: NaiveDateTime,
+}
+
+/// The first request made with an idempotency key, and what it returned.
+#[derive(Queryable, PartialEq, Debug)]
+#[diesel(table_name = idempotency_keys)]
+pub struct IdempotentRequest {
+    pub user_id: i32,
+    pub key: String,
+    pub request: String,
+    pub response: Option<serde_json::Value>,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Queryable, PartialEq, Eq, Debug, Insertable, Associations)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = sessions)]
+pub struct Session {
+    pub token_hash: String,
+    pub user_id: i32,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DbEnum, FromFormField, Deserialize, Serialize)]
+#[DieselTypePath = "crate::schema::sql_types::AuditAction"]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Queryable, PartialEq, Debug, Identifiable, Serialize)]
+#[diesel(table_name = audit_log)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub occurred_at: NaiveDateTime,
+    pub entity: String,
+    pub entity_id: i32,
+    pub action: AuditAction,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DbEnum, Deserialize, Serialize)]
+#[DieselTypePath = "crate::schema::sql_types::StockMovementReason"]
+#[serde(rename_all = "snake_case")]
+pub enum StockMovementReason {
+    Receipt,
+    Sale,
+    Adjustment,
+    Damage,
+    Transfer,
+    Return,
+}
+
+#[derive(Queryable, PartialEq, Debug, Identifiable, Serialize, Deserialize)]
+#[diesel(table_name = stock_movements)]
+pub struct StockMovement {
+    pub id: i32,
+    pub product_id: i32,
+    pub quantity: f64,
+    pub reason: StockMovementReason,
+    pub order_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub occurred_at: NaiveDateTime,
+}
+
+#[derive(FromForm, Default)]
+pub struct AuditFilter {
+    pub user_id: Option<i32>,
+    pub entity: Option<String>,
+    pub entity_id: Option<i32>,
+    pub action: Option<AuditAction>,
+    /// Unix timestamps bounding `occurred_at`
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField)]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductColumn {
+    #[default]
+    Id,
+    Upc,
+    Name,
+    Description,
+    Amount,
+    #[field(value = "case_size")]
+    CaseSize,
+    #[field(value = "measure_by_weight")]
+    MeasureByWeight,
+    #[field(value = "cost_price_per_unit")]
+    CostPricePerUnit,
+    #[field(value = "selling_price_per_unit")]
+    SellingPricePerUnit,
+    #[field(value = "sale_end")]
+    SaleEnd,
+    #[field(value = "buy_level")]
+    BuyLevel,
+    #[field(value = "sale_price")]
+    SalePrice,
+    #[field(value = "brand_id")]
+    BrandId,
+    #[field(value = "updated_at")]
+    UpdatedAt,
+}
+
+#[derive(FromForm, Default)]
+pub struct ProductFilter {
+    pub category: Option<i32>,
+    pub brand: Option<i32>,
+    pub supplier: Option<i32>,
+    /// Whether a sale price applies right now
+    pub on_sale: Option<bool>,
+    /// Whether `amount` is under `buy_level`
+    pub below_buy_level: Option<bool>,
+    pub sort: Option<ProductColumn>,
+    pub direction: Option<SortDirection>,
+}
+
+/// The related records to embed in product responses, parsed from a comma
+/// separated list such as `include=brand,categories,suppliers`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProductIncludes {
+    pub brand: bool,
+    pub categories: bool,
+    pub suppliers: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> rocket::form::FromFormField<'r> for ProductIncludes {
+    fn from_value(field: rocket::form::ValueField<'r>) -> rocket::form::Result<'r, Self> {
+        let mut includes: ProductIncludes = Default::default();
+        for relation in field
+            .value
+            .split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+        {
+            match relation {
+                "brand" => includes.brand = true,
+                "categories" => includes.categories = true,
+                "suppliers" => includes.suppliers = true,
+                _ => Err(rocket::form::Error::validation(format!(
+                    "cannot include {}",
+                    relation
+                )))?,
+            }
+        }
+        Ok(includes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CategoryColumn {
+    #[default]
+    Id,
+    Name,
+    #[field(value = "updated_at")]
+    UpdatedAt,
+}
+
+#[derive(FromForm, Default)]
+pub struct CategoryFilter {
+    /// Matches names containing this, ignoring case
+    pub name: Option<String>,
+    pub sort: Option<CategoryColumn>,
+    pub direction: Option<SortDirection>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SupplierColumn {
+    #[default]
+    Id,
+    Name,
+    #[field(value = "phone_number")]
+    PhoneNumber,
+    Email,
+    #[field(value = "updated_at")]
+    UpdatedAt,
+}
+
+#[derive(FromForm, Default)]
+pub struct SupplierFilter {
+    /// Matches names containing this, ignoring case
+    pub name: Option<String>,
+    pub sort: Option<SupplierColumn>,
+    pub direction: Option<SortDirection>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingOrderColumn {
+    #[default]
+    Id,
+    #[field(value = "product_id")]
+    ProductId,
+    Amount,
+    #[field(value = "updated_at")]
+    UpdatedAt,
+}
+
+#[derive(FromForm, Default)]
+pub struct PendingOrderFilter {
+    pub product: Option<i32>,
+    pub sort: Option<PendingOrderColumn>,
+    pub direction: Option<SortDirection>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceivedOrderColumn {
+    #[default]
+    Id,
+    Received,
+    #[field(value = "product_id")]
+    ProductId,
+    #[field(value = "gross_amount")]
+    GrossAmount,
+    #[field(value = "actually_received")]
+    ActuallyReceived,
+    Damaged,
+    #[field(value = "updated_at")]
+    UpdatedAt,
+}
+
+#[derive(FromForm, Default)]
+pub struct ReceivedOrderFilter {
+    pub product: Option<i32>,
+    /// Unix timestamps bounding `received`
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub sort: Option<ReceivedOrderColumn>,
+    pub direction: Option<SortDirection>,
+}
+
 pub struct UserBuilder {
     pub name: String,
     pub email: String,
     pub password: String,
-    pub permissions: PermissionsBuilder,
+    pub roles: Vec<i32>,
     pub preferences: PreferencesBuilder,
 }
 
@@ -120,8 +824,8 @@ impl UserBuilder {
         UserBuilder {
             name,
             email: String::new(),
-            password: hash(password.to_string() + PEPPER, DEFAULT_COST as u32).unwrap(),
-            permissions: PermissionsBuilder::default(),
+            password: hash(password.to_string() + &pepper(), DEFAULT_COST as u32).unwrap(),
+            roles: Vec::new(),
             preferences: PreferencesBuilder::default(),
         }
     }
@@ -131,8 +835,8 @@ impl UserBuilder {
         self
     }
 
-    pub fn with_permissions(mut self, perms: PermissionsBuilder) -> Self {
-        self.permissions = perms;
+    pub fn with_roles(mut self, roles: &[i32]) -> Self {
+        self.roles = roles.to_vec();
         self
     }
     pub fn with_preferences(mut self, preferences: PreferencesBuilder) -> Self {
@@ -140,157 +844,165 @@ impl UserBuilder {
         self
     }
 
-    pub async fn build(self, conn: &mut AsyncPgConnection) -> i32 {
-        let user_id = crate::schema::users::dsl::users
-            .select(crate::schema::users::dsl::id)
-            .load::<i32>(conn)
-            .await
-            .unwrap()
-            .into_iter()
-            .max()
-            .unwrap_or(0)
-            + 1;
-        let row = User {
-            id: user_id,
-            name: self.name,
-            password: self.password,
-            email: self.email,
-        };
-        diesel::insert_into(crate::schema::users::dsl::users)
-            .values(row)
-            .execute(conn)
-            .await
-            .unwrap();
+    pub async fn build(self, conn: &mut AsyncPgConnection, actor: i32) -> QueryResult<i32> {
+        let row: User = diesel::insert_into(crate::schema::users::dsl::users)
+            .values(NewUserRow {
+                name: self.name,
+                password: self.password,
+                email: self.email,
+            })
+            .get_result(conn)
+            .await?;
+        let user_id = row.id;
+        AuditEntry::record(conn, actor, "user", user_id, None, Some(&row)).await?;
 
         let row = Preference { user_id };
         diesel::insert_into(crate::schema::preferences::dsl::preferences)
             .values(row)
             .execute(conn)
-            .await
-            .unwrap();
+            .await?;
 
-        let row = Permission {
-            user_id,
-            admin: self.permissions.admin,
-            create_orders: self.permissions.create_orders,
-            edit_pending: self.permissions.edit_pending,
-            edit_products: self.permissions.edit_products,
-            view_pending: self.permissions.view_pending,
-            edit_received: self.permissions.edit_received,
-            view_received: self.permissions.view_received,
-            remove_orders: self.permissions.remove_orders,
-            view_products: self.permissions.view_products,
-            view_suppliers: self.permissions.view_suppliers,
-        };
-        diesel::insert_into(crate::schema::permissions::dsl::permissions)
-            .values(row)
-            .execute(conn)
-            .await
-            .unwrap();
-        user_id
+        User::set_roles(conn, actor, user_id, &self.roles).await?;
+        Ok(user_id)
     }
 }
 
-#[derive(Default)]
-pub struct PermissionsBuilder {
-    pub admin: bool,
-    pub view_pending: bool,
-    pub view_received: bool,
-    pub edit_pending: bool,
-    pub create_orders: bool,
-    pub edit_received: bool,
-    pub remove_orders: bool,
-    pub edit_products: bool,
-    pub view_products: bool,
-    pub view_suppliers: bool,
-}
-
 #[derive(Default)]
 pub struct PreferencesBuilder {}
 impl User {
-    pub async fn from_name(conn: &mut AsyncPgConnection, username: &str) -> Self {
+    pub async fn from_name(conn: &mut AsyncPgConnection, username: &str) -> QueryResult<Self> {
         crate::schema::users::dsl::users
             .filter(crate::schema::users::dsl::name.eq(username))
             .first(conn)
             .await
-            .unwrap()
     }
 
     pub async fn get(
         conn: &mut AsyncPgConnection,
         username: &str,
         userpassword: &str,
-    ) -> Option<Self> {
+    ) -> QueryResult<Option<Self>> {
         let user: User = crate::schema::users::dsl::users
             .filter(crate::schema::users::dsl::name.eq(username))
             .first(conn)
-            .await
-            .unwrap();
-        match verify(userpassword.to_string() + PEPPER, &user.password).unwrap() {
-            true => Some(user),
-            false => None,
+            .await?;
+        match verify(userpassword.to_string() + &pepper(), &user.password) {
+            Ok(true) => Ok(Some(user)),
+            _ => Ok(None),
         }
     }
 
-    pub async fn from_id(conn: &mut AsyncPgConnection, id: i32) -> Self {
+    pub async fn from_id(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
         crate::schema::users::dsl::users
             .filter(crate::schema::users::dsl::id.eq(id))
             .first(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn update(self, conn: &mut AsyncPgConnection) {
-        diesel::update(
-            crate::schema::users::dsl::users.filter(crate::schema::users::dsl::id.eq(self.id)),
+    /// Writes only the fields set in `changes`. Given a `version`, returns
+    /// `None` without writing anything if the user changed since then.
+    pub async fn patch(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+        version: Option<NaiveDateTime>,
+        changes: UserChanges,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = users::table.find(id).for_update().first(conn).await?;
+        // Setting `updated_at` as well keeps an empty changeset from being an error
+        let after: Option<Self> = diesel::update(
+            users::table
+                .find(id)
+                .filter(users::updated_at.eq(version.unwrap_or(before.updated_at))),
         )
-        .set((
-            crate::schema::users::dsl::name.eq(self.name),
-            crate::schema::users::dsl::email.eq(self.email),
-            crate::schema::users::dsl::password.eq(self.password),
-        ))
-        .execute(conn)
+        .set((&changes, users::updated_at.eq(diesel::dsl::now)))
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(conn, actor, "user", id, Some(&before), Some(&after)).await?;
+        Ok(Some(after))
     }
 
-    pub async fn delete(conn: &mut AsyncPgConnection, id: i32) {
-        diesel::delete(
-            crate::schema::users::dsl::users.filter(crate::schema::users::dsl::id.eq(id)),
-        )
-        .execute(conn)
-        .await
-        .unwrap();
+    pub async fn delete(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        let before = User::from_id(conn, id).await?;
         diesel::delete(
-            crate::schema::permissions::dsl::permissions
-                .filter(crate::schema::permissions::dsl::user_id.eq(id)),
+            crate::schema::preferences::dsl::preferences
+                .filter(crate::schema::preferences::dsl::user_id.eq(id)),
         )
         .execute(conn)
-        .await
-        .unwrap();
+        .await?;
+        ApiKey::delete_created_by(conn, actor, id).await?;
         diesel::delete(
-            crate::schema::preferences::dsl::preferences
-                .filter(crate::schema::preferences::dsl::user_id.eq(id)),
+            crate::schema::users::dsl::users.filter(crate::schema::users::dsl::id.eq(id)),
         )
         .execute(conn)
-        .await
-        .unwrap();
+        .await?;
+        AuditEntry::record(conn, actor, "user", id, Some(&before), None).await
+    }
+
+    pub async fn get_permissions(&self, conn: &mut AsyncPgConnection) -> QueryResult<PermissionSet> {
+        Ok(role_permissions::table
+            .inner_join(user_roles::table.on(user_roles::role_id.eq(role_permissions::role_id)))
+            .filter(user_roles::user_id.eq(self.id))
+            .select(role_permissions::permission)
+            .distinct()
+            .load::<String>(conn)
+            .await?
+            .into_iter()
+            .collect())
     }
 
-    pub async fn get_permissions(&self, conn: &mut AsyncPgConnection) -> Permission {
-        crate::schema::permissions::dsl::permissions
-            .filter(crate::schema::permissions::dsl::user_id.eq(self.id))
-            .first(conn)
+    pub async fn get_roles(&self, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Role>> {
+        roles::table
+            .inner_join(user_roles::table)
+            .filter(user_roles::user_id.eq(self.id))
+            .select((roles::id, roles::name))
+            .load(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn get_preferences(&self, conn: &mut AsyncPgConnection) -> Preference {
+    /// Replaces every role the user holds with `role_ids`.
+    pub async fn set_roles(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        user_id: i32,
+        role_ids: &[i32],
+    ) -> QueryResult<()> {
+        let before: Vec<i32> =
+            diesel::delete(user_roles::table.filter(user_roles::user_id.eq(user_id)))
+                .returning(user_roles::role_id)
+                .get_results(conn)
+                .await?;
+        let rows = role_ids
+            .iter()
+            .map(|role_id| (user_roles::user_id.eq(user_id), user_roles::role_id.eq(role_id)))
+            .collect::<Vec<_>>();
+        if !rows.is_empty() {
+            diesel::insert_into(user_roles::table)
+                .values(rows)
+                .execute(conn)
+                .await?;
+        }
+        AuditEntry::record(
+            conn,
+            actor,
+            "user_roles",
+            user_id,
+            Some(&before),
+            Some(&role_ids.to_vec()),
+        )
+        .await
+    }
+
+    pub async fn get_preferences(&self, conn: &mut AsyncPgConnection) -> QueryResult<Preference> {
         crate::schema::preferences::dsl::preferences
             .filter(crate::schema::preferences::dsl::user_id.eq(self.id))
             .first(conn)
             .await
-            .unwrap()
     }
 }
 
@@ -362,285 +1074,570 @@ impl ProductBuilder {
         self
     }
 
-    pub async fn build(self, conn: &mut AsyncPgConnection) -> i32 {
-        let product_id = crate::schema::products::dsl::products
-            .select(crate::schema::products::dsl::id)
-            .load::<i32>(conn)
-            .await
-            .unwrap()
-            .into_iter()
-            .max()
-            .unwrap_or(0)
-            + 1;
-        let row = Product {
-            id: product_id,
-            upc: self.upc,
-            name: self.name,
-            amount: 0.0,
-            case_size: self.case_size,
-            description: self.description.unwrap_or_default(),
-            cost_price_per_unit: self.cost_price_per_unit,
-            selling_price_per_unit: self.selling_price_per_unit,
-            measure_by_weight: self.measure_by_weight,
-            sale_end: None,
-            sale_price: None,
-            buy_level: self.buy_level,
-        };
-        diesel::insert_into(crate::schema::products::dsl::products)
-            .values(row)
-            .execute(conn)
-            .await
-            .unwrap();
-
-        if let Some(brand) = self.brand {
-            let mut products: Vec<Option<i32>> = crate::schema::brands::dsl::brands
-                .filter(crate::schema::brands::dsl::id.eq(brand))
-                .select(crate::schema::brands::dsl::products)
-                .first(conn)
-                .await
-                .unwrap();
-            products.push(Some(product_id));
-            diesel::update(
-                crate::schema::brands::dsl::brands.filter(crate::schema::brands::dsl::id.eq(brand)),
-            )
-            .set(crate::schema::brands::dsl::products.eq(products))
-            .execute(conn)
-            .await
-            .unwrap();
-        };
+    pub async fn build(self, conn: &mut AsyncPgConnection, actor: i32) -> QueryResult<i32> {
+        let row: Product = diesel::insert_into(crate::schema::products::dsl::products)
+            .values(NewProductRow {
+                upc: self.upc,
+                name: self.name,
+                amount: 0.0,
+                case_size: self.case_size,
+                description: self.description.unwrap_or_default(),
+                cost_price_per_unit: self.cost_price_per_unit,
+                selling_price_per_unit: self.selling_price_per_unit,
+                measure_by_weight: self.measure_by_weight,
+                sale_end: None,
+                sale_price: None,
+                buy_level: self.buy_level,
+                brand_id: self.brand,
+            })
+            .get_result(conn)
+            .await?;
+        let product_id = row.id;
+        AuditEntry::record(conn, actor, "product", product_id, None, Some(&row)).await?;
 
         for category in self.categories.into_iter() {
-            let mut products: Vec<Option<i32>> = crate::schema::categories::dsl::categories
-                .filter(crate::schema::categories::dsl::id.eq(category))
-                .select(crate::schema::categories::dsl::products)
-                .first(conn)
-                .await
-                .unwrap();
-            products.push(Some(product_id));
-            diesel::update(
-                crate::schema::categories::dsl::categories
-                    .filter(crate::schema::categories::dsl::id.eq(category)),
-            )
-            .set(crate::schema::categories::dsl::products.eq(products))
-            .execute(conn)
-            .await
-            .unwrap();
+            row.add_category(conn, actor, category).await?;
         }
 
         for supplier in self.suppliers.into_iter() {
-            let mut products: Vec<Option<i32>> = crate::schema::suppliers::dsl::suppliers
-                .filter(crate::schema::suppliers::dsl::id.eq(supplier))
-                .select(crate::schema::suppliers::dsl::products)
-                .first(conn)
-                .await
-                .unwrap();
-            products.push(Some(product_id));
-            diesel::update(
-                crate::schema::suppliers::dsl::suppliers
-                    .filter(crate::schema::suppliers::dsl::id.eq(supplier)),
-            )
-            .set(crate::schema::suppliers::dsl::products.eq(products))
-            .execute(conn)
-            .await
-            .unwrap();
+            row.add_supplier(conn, actor, supplier).await?;
         }
-        product_id
+        Ok(product_id)
     }
 }
 
 impl Product {
-    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> Self {
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
         crate::schema::products::dsl::products
             .filter(crate::schema::products::dsl::id.eq(id))
             .first(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn update(self, conn: &mut AsyncPgConnection) {
-        diesel::update(
+    /// Returns `None` without writing anything if the product changed since
+    /// `self.updated_at`. A changed `amount` is recorded as an adjustment in the
+    /// stock ledger rather than written directly.
+    pub async fn update(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = products::table
+            .find(self.id)
+            .for_update()
+            .first(conn)
+            .await?;
+        let after: Option<Self> = diesel::update(
             crate::schema::products::dsl::products
-                .filter(crate::schema::products::dsl::id.eq(self.id)),
+                .filter(crate::schema::products::dsl::id.eq(self.id))
+                .filter(crate::schema::products::dsl::updated_at.eq(self.updated_at)),
         )
         .set((
-            crate::schema::products::dsl::upc.eq(self.upc),
-            crate::schema::products::dsl::name.eq(self.name),
-            crate::schema::products::dsl::description.eq(self.description),
-            crate::schema::products::dsl::amount.eq(self.amount),
+            crate::schema::products::dsl::upc.eq(&self.upc),
+            crate::schema::products::dsl::name.eq(&self.name),
+            crate::schema::products::dsl::description.eq(&self.description),
             crate::schema::products::dsl::buy_level.eq(self.buy_level),
             crate::schema::products::dsl::case_size.eq(self.case_size),
             crate::schema::products::dsl::measure_by_weight.eq(self.measure_by_weight),
-            crate::schema::products::dsl::cost_price_per_unit.eq(self.cost_price_per_unit),
-            crate::schema::products::dsl::selling_price_per_unit.eq(self.selling_price_per_unit),
+            crate::schema::products::dsl::cost_price_per_unit.eq(&self.cost_price_per_unit),
+            crate::schema::products::dsl::selling_price_per_unit.eq(&self.selling_price_per_unit),
             crate::schema::products::dsl::sale_end.eq(self.sale_end),
-            crate::schema::products::dsl::sale_price.eq(self.sale_price),
+            crate::schema::products::dsl::sale_price.eq(&self.sale_price),
+            crate::schema::products::dsl::brand_id.eq(self.brand_id),
         ))
-        .execute(conn)
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(conn, actor, "product", self.id, Some(&before), Some(&after)).await?;
+        if self.amount != before.amount {
+            StockMovement::record(
+                conn,
+                actor,
+                self.id,
+                self.amount - before.amount,
+                StockMovementReason::Adjustment,
+                None,
+            )
+            .await?;
+            return Product::get(conn, self.id).await.map(Some);
+        }
+        Ok(Some(after))
+    }
+
+    /// Writes only the fields set in `changes`. Given a `version`, returns
+    /// `None` without writing anything if the product changed since then.
+    pub async fn patch(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+        version: Option<NaiveDateTime>,
+        changes: ProductChanges,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = products::table.find(id).for_update().first(conn).await?;
+        // Setting `updated_at` as well keeps an empty changeset from being an error
+        let after: Option<Self> = diesel::update(
+            products::table
+                .find(id)
+                .filter(products::updated_at.eq(version.unwrap_or(before.updated_at))),
+        )
+        .set((&changes, products::updated_at.eq(diesel::dsl::now)))
+        .get_result(conn)
+        .await
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(conn, actor, "product", id, Some(&before), Some(&after)).await?;
+        Ok(Some(after))
+    }
+
+    pub async fn get_stock_movements(
+        &self,
+        conn: &mut AsyncPgConnection,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> QueryResult<Vec<StockMovement>> {
+        let mut query = stock_movements::table
+            .filter(stock_movements::product_id.eq(self.id))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(stock_movements::id.gt(after.id));
+        }
+        query
+            .order(stock_movements::id)
+            .limit(limit)
+            .load(conn)
+            .await
+    }
+
+    pub async fn add_supplier(
+        &self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+    ) -> QueryResult<()> {
+        Supplier::get(conn, id).await?;
+        let link = ProductSupplier {
+            product_id: self.id,
+            supplier_id: id,
+        };
+        let added = diesel::insert_into(product_suppliers::table)
+            .values(&link)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+        if added > 0 {
+            AuditEntry::record(conn, actor, "product_supplier", self.id, None, Some(&link)).await?;
+        }
+        Ok(())
     }
 
-    pub async fn add_supplier(&self, conn: &mut AsyncPgConnection, id: i32) {
-        let mut supplier = Supplier::get(conn, id).await;
-        supplier.products.push(Some(self.id));
-        supplier.update(conn).await;
+    pub async fn add_category(
+        &self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+    ) -> QueryResult<()> {
+        Category::get(conn, id).await?;
+        let link = ProductCategory {
+            product_id: self.id,
+            category_id: id,
+        };
+        let added = diesel::insert_into(product_categories::table)
+            .values(&link)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+        if added > 0 {
+            AuditEntry::record(conn, actor, "product_category", self.id, None, Some(&link)).await?;
+        }
+        Ok(())
     }
 
-    pub async fn add_category(&self, conn: &mut AsyncPgConnection, id: i32) {
-        let mut category = Category::get(conn, id).await;
-        category.products.push(Some(self.id));
-        category.update(conn).await;
+    pub async fn add_brand(
+        &self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+    ) -> QueryResult<()> {
+        Brand::get(conn, id).await?;
+        self.set_brand(conn, actor, Some(id)).await
     }
 
-    pub async fn add_brand(&self, conn: &mut AsyncPgConnection, id: i32) {
-        let mut brand = Brand::get(conn, id).await;
-        brand.products.push(Some(self.id));
-        brand.update(conn).await;
+    pub async fn remove_supplier(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+    ) -> QueryResult<()> {
+        let removed: Option<ProductSupplier> =
+            diesel::delete(product_suppliers::table.find((self.id, id)))
+                .get_result(conn)
+                .await
+                .optional()?;
+        match removed {
+            Some(link) => {
+                AuditEntry::record(conn, actor, "product_supplier", self.id, Some(&link), None)
+                    .await
+            }
+            None => Ok(()),
+        }
     }
 
-    pub async fn remove_supplier(self, conn: &mut AsyncPgConnection, id: i32) {
-        let mut supplier = Supplier::get(conn, id).await;
-        supplier
-            .products
-            .retain(|product| product.unwrap() != self.id);
-        supplier.update(conn).await;
+    pub async fn remove_category(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+    ) -> QueryResult<()> {
+        let removed: Option<ProductCategory> =
+            diesel::delete(product_categories::table.find((self.id, id)))
+                .get_result(conn)
+                .await
+                .optional()?;
+        match removed {
+            Some(link) => {
+                AuditEntry::record(conn, actor, "product_category", self.id, Some(&link), None)
+                    .await
+            }
+            None => Ok(()),
+        }
     }
 
-    pub async fn remove_category(self, conn: &mut AsyncPgConnection, id: i32) {
-        let mut category = Category::get(conn, id).await;
-        category
-            .products
-            .retain(|product| product.unwrap() != self.id);
-        category.update(conn).await;
+    pub async fn remove_brand(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+    ) -> QueryResult<()> {
+        if self.brand_id == Some(id) {
+            self.set_brand(conn, actor, None).await?;
+        }
+        Ok(())
     }
 
-    pub async fn remove_brand(self, conn: &mut AsyncPgConnection, id: i32) {
-        let mut brand = Brand::get(conn, id).await;
-        brand.products.retain(|brand| brand.unwrap() != self.id);
-        brand.update(conn).await;
+    async fn set_brand(
+        &self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        brand_id: Option<i32>,
+    ) -> QueryResult<()> {
+        let after: Product = diesel::update(
+            crate::schema::products::dsl::products
+                .filter(crate::schema::products::dsl::id.eq(self.id)),
+        )
+        .set(crate::schema::products::dsl::brand_id.eq(brand_id))
+        .get_result(conn)
+        .await?;
+        AuditEntry::record(conn, actor, "product", self.id, Some(self), Some(&after)).await
     }
 
-    pub async fn delete(self, conn: &mut AsyncPgConnection) {
+    pub async fn delete(self, conn: &mut AsyncPgConnection, actor: i32) -> QueryResult<()> {
         let id = self.id;
-        diesel::delete(
+        let pending_orders: Vec<PendingOrder> = diesel::delete(
             crate::schema::pending_orders::dsl::pending_orders
                 .filter(crate::schema::pending_orders::dsl::product_id.eq(id)),
         )
-        .execute(conn)
-        .await
-        .unwrap();
-        diesel::delete(
+        .get_results(conn)
+        .await?;
+        for order in pending_orders {
+            AuditEntry::record(conn, actor, "pending_order", order.id, Some(&order), None).await?;
+        }
+        let received_orders: Vec<ReceivedOrder> = diesel::delete(
             crate::schema::received_orders::dsl::received_orders
                 .filter(crate::schema::received_orders::dsl::product_id.eq(id)),
         )
-        .execute(conn)
-        .await
-        .unwrap();
-        let suppliers: Vec<Supplier> = crate::schema::suppliers::dsl::suppliers
-            .filter(crate::schema::suppliers::dsl::products.contains(vec![id]))
-            .load(conn)
-            .await
-            .unwrap();
-        for supplier in suppliers {
-            let mut products: Vec<Option<i32>> = crate::schema::suppliers::dsl::suppliers
-                .filter(crate::schema::suppliers::dsl::id.eq(supplier.id))
-                .select(crate::schema::suppliers::dsl::products)
-                .first(conn)
-                .await
-                .unwrap();
-            products.retain(|&product| product.unwrap() != id);
-            diesel::update(
-                crate::schema::suppliers::dsl::suppliers
-                    .filter(crate::schema::suppliers::dsl::id.eq(supplier.id)),
-            )
-            .set(crate::schema::suppliers::dsl::products.eq(products)
-                )
-            .execute(conn)
-            .await
-            .unwrap();
+        .get_results(conn)
+        .await?;
+        for order in received_orders {
+            AuditEntry::record(conn, actor, "received_order", order.id, Some(&order), None).await?;
+        }
+        let suppliers: Vec<ProductSupplier> =
+            diesel::delete(product_suppliers::table.filter(product_suppliers::product_id.eq(id)))
+                .get_results(conn)
+                .await?;
+        for link in suppliers {
+            AuditEntry::record(conn, actor, "product_supplier", id, Some(&link), None).await?;
+        }
+        let categories: Vec<ProductCategory> =
+            diesel::delete(product_categories::table.filter(product_categories::product_id.eq(id)))
+                .get_results(conn)
+                .await?;
+        for link in categories {
+            AuditEntry::record(conn, actor, "product_category", id, Some(&link), None).await?;
         }
-
-        let brand: Brand = match crate::schema::brands::dsl::brands
-            .filter(crate::schema::brands::dsl::products.contains(vec![id]))
-            .first(conn)
-            .await
-        {
-            Ok(val) => val,
-            Err(_) => {
-                diesel::delete(
-                    crate::schema::products::dsl::products
-                        .filter(crate::schema::products::dsl::id.eq(id)),
-                )
-                .execute(conn)
-                .await
-                .unwrap();
-                return;
-            }
-        };
-        {
-            let mut products: Vec<Option<i32>> = crate::schema::brands::dsl::brands
-                .filter(crate::schema::brands::dsl::id.eq(brand.id))
-                .select(crate::schema::brands::dsl::products)
-                .first(conn)
-                .await
-                .unwrap();
-            products.retain(|&product| product.unwrap() != id);
-            diesel::update(
-                crate::schema::brands::dsl::brands
-                    .filter(crate::schema::brands::dsl::id.eq(brand.id)),
-            )
-            .set(crate::schema::brands::dsl::products.eq(products))
-            .execute(conn)
-            .await
-            .unwrap()
-        };
         diesel::delete(
             crate::schema::products::dsl::products.filter(crate::schema::products::dsl::id.eq(id)),
         )
         .execute(conn)
-        .await
-        .unwrap();
+        .await?;
+        AuditEntry::record(conn, actor, "product", id, Some(&self), None).await
+    }
+
+    fn filtered(filter: &ProductFilter) -> products::BoxedQuery<'static, Pg> {
+        let mut query = products::table.into_boxed();
+        if let Some(category) = filter.category {
+            query = query.filter(
+                products::id.eq_any(
+                    product_categories::table
+                        .select(product_categories::product_id)
+                        .filter(product_categories::category_id.eq(category)),
+                ),
+            );
+        }
+        if let Some(supplier) = filter.supplier {
+            query = query.filter(
+                products::id.eq_any(
+                    product_suppliers::table
+                        .select(product_suppliers::product_id)
+                        .filter(product_suppliers::supplier_id.eq(supplier)),
+                ),
+            );
+        }
+        if let Some(brand) = filter.brand {
+            query = query.filter(products::brand_id.eq(brand));
+        }
+        if let Some(on_sale) = filter.on_sale {
+            let sale = products::sale_price.is_not_null().and(
+                products::sale_end
+                    .is_null()
+                    .or(products::sale_end.gt(diesel::dsl::now)),
+            );
+            query = match on_sale {
+                true => query.filter(sale),
+                false => query.filter(diesel::dsl::not(sale)),
+            };
+        }
+        if let Some(below_buy_level) = filter.below_buy_level {
+            let below = products::buy_level.gt(products::amount.nullable());
+            query = match below_buy_level {
+                true => query.filter(below),
+                false => query.filter(products::buy_level.is_null().or(diesel::dsl::not(below))),
+            };
+        }
+        query
     }
 
-    pub async fn get_all(conn: &mut AsyncPgConnection, limit: i64, offset: i64) -> Vec<Self> {
-        crate::schema::products::dsl::products
-            .limit(limit)
-            .offset(offset)
+    /// Loads up to `limit` products matching `filter`, starting after the row
+    /// `after` points at.
+    pub async fn search(
+        conn: &mut AsyncPgConnection,
+        filter: &ProductFilter,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        let direction = filter.direction.unwrap_or_default();
+        let query = Self::filtered(filter);
+        let query = match filter.sort.unwrap_or_default() {
+            ProductColumn::Id => sort_by!(query, direction, after, products::id: i32),
+            ProductColumn::Upc => sort_by!(query, direction, after, products::upc: String),
+            ProductColumn::Name => sort_by!(query, direction, after, products::name: String),
+            ProductColumn::Description => {
+                sort_by!(query, direction, after, products::description: String)
+            }
+            ProductColumn::Amount => sort_by!(query, direction, after, products::amount: f64),
+            ProductColumn::CaseSize => {
+                sort_by_nullable!(query, direction, after, products::case_size: i32)
+            }
+            ProductColumn::MeasureByWeight => {
+                sort_by!(query, direction, after, products::measure_by_weight: bool)
+            }
+            ProductColumn::CostPricePerUnit => {
+                sort_by!(query, direction, after, products::cost_price_per_unit: BigDecimal)
+            }
+            ProductColumn::SellingPricePerUnit => {
+                sort_by!(query, direction, after, products::selling_price_per_unit: BigDecimal)
+            }
+            ProductColumn::SaleEnd => {
+                sort_by_nullable!(query, direction, after, products::sale_end: NaiveDateTime)
+            }
+            ProductColumn::BuyLevel => {
+                sort_by_nullable!(query, direction, after, products::buy_level: f64)
+            }
+            ProductColumn::SalePrice => {
+                sort_by_nullable!(query, direction, after, products::sale_price: BigDecimal)
+            }
+            ProductColumn::BrandId => {
+                sort_by_nullable!(query, direction, after, products::brand_id: i32)
+            }
+            ProductColumn::UpdatedAt => {
+                sort_by!(query, direction, after, products::updated_at: NaiveDateTime)
+            }
+        };
+        query.limit(limit).load(conn).await
+    }
+
+    pub async fn count(conn: &mut AsyncPgConnection, filter: &ProductFilter) -> QueryResult<i64> {
+        Self::filtered(filter).count().get_result(conn).await
+    }
+
+    pub async fn get_names(
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(String, String, i32)>> {
+        products::table
+            .select((products::name, products::upc, products::id))
             .load(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn get_names(conn: &mut AsyncPgConnection) -> Vec<(String, String, i32)> {
-        crate::schema::products::dsl::products.load(conn).await.unwrap().into_iter().map(|product: Product| (product.name, product.upc, product.id)).collect()
+    /// Full-text search over the name, description and UPC, best matches first.
+    /// Every word matches as a prefix, so results show up while the last one is
+    /// still being typed.
+    pub async fn search_text(
+        conn: &mut AsyncPgConnection,
+        text: &str,
+        limit: i64,
+    ) -> QueryResult<Vec<ProductMatch>> {
+        let words: Vec<String> = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| format!("{}:*", word.to_lowercase()))
+            .collect();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        diesel::sql_query(format!(
+            "SELECT products.*, \
+                 ts_rank({document}, query) AS rank, \
+                 ts_headline('english', name, query, '{marks}, HighlightAll=true') AS name_highlight, \
+                 ts_headline('english', description, query, '{marks}') AS description_highlight \
+             FROM products, to_tsquery('english', $1) AS query \
+             WHERE {document} @@ query \
+             ORDER BY rank DESC, id \
+             LIMIT $2",
+            document = PRODUCT_DOCUMENT,
+            marks = "StartSel=<mark>, StopSel=</mark>",
+        ))
+        .bind::<diesel::sql_types::Text, _>(words.join(" & "))
+        .bind::<diesel::sql_types::BigInt, _>(limit)
+        .load(conn)
+        .await
     }
 
-    pub async fn get_categories(&self, conn: &mut AsyncPgConnection) -> Vec<Category> {
-        crate::schema::categories::dsl::categories
-            .filter(crate::schema::categories::dsl::products.contains(vec![self.id]))
+    /// Products named like `text`, most similar first. Forgives typos and
+    /// missing spaces or punctuation.
+    pub async fn search_similar(
+        conn: &mut AsyncPgConnection,
+        text: &str,
+        limit: i64,
+    ) -> QueryResult<Vec<Similar<Self>>> {
+        let rows: Vec<(Self, f32)> = products::table
+            .filter(word_similarity(text, products::name).ge(WORD_SIMILARITY))
+            .select((products::all_columns, word_similarity(text, products::name)))
+            .order((word_similarity(text, products::name).desc(), products::id))
+            .limit(limit)
             .load(conn)
-            .await
-            .unwrap()
+            .await?;
+        Ok(rows.into_iter().map(Similar::from).collect())
     }
 
-    pub async fn get_suppliers(&self, conn: &mut AsyncPgConnection) -> Vec<Supplier> {
-        crate::schema::suppliers::dsl::suppliers
-            .filter(crate::schema::suppliers::dsl::products.contains(vec![self.id]))
+    pub async fn get_categories(&self, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Category>> {
+        categories::table
+            .inner_join(product_categories::table)
+            .filter(product_categories::product_id.eq(self.id))
+            .select(categories::all_columns)
             .load(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn get_brand(&self, conn: &mut AsyncPgConnection) -> Option<Brand> {
-        match crate::schema::brands::dsl::brands
-            .filter(crate::schema::brands::dsl::products.contains(vec![self.id]))
-            .first(conn)
+    pub async fn get_suppliers(&self, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Supplier>> {
+        suppliers::table
+            .inner_join(product_suppliers::table)
+            .filter(product_suppliers::product_id.eq(self.id))
+            .select(suppliers::all_columns)
+            .load(conn)
             .await
-        {
-            Ok(val) => Some(val),
-            Err(_) => None,
+    }
+
+    pub async fn get_brand(&self, conn: &mut AsyncPgConnection) -> QueryResult<Option<Brand>> {
+        match self.brand_id {
+            Some(brand_id) => Brand::get(conn, brand_id).await.optional(),
+            None => Ok(None),
+        }
+    }
+
+    /// Attaches the requested relations to `products`, with one query per
+    /// relation however many products there are.
+    pub async fn with_relations(
+        conn: &mut AsyncPgConnection,
+        products: Vec<Product>,
+        includes: ProductIncludes,
+    ) -> QueryResult<Vec<ProductWithRelations>> {
+        let ids: Vec<i32> = products.iter().map(|product| product.id).collect();
+
+        let mut brands = None;
+        if includes.brand {
+            let brand_ids: Vec<i32> = products
+                .iter()
+                .filter_map(|product| product.brand_id)
+                .collect();
+            let rows: Vec<Brand> = brands::table
+                .filter(brands::id.eq_any(brand_ids))
+                .load(conn)
+                .await?;
+            brands = Some(
+                rows.into_iter()
+                    .map(|brand| (brand.id, brand))
+                    .collect::<HashMap<_, _>>(),
+            );
+        }
+
+        let mut categories = None;
+        if includes.categories {
+            let rows: Vec<(i32, Category)> = categories::table
+                .inner_join(product_categories::table)
+                .filter(product_categories::product_id.eq_any(&ids))
+                .select((product_categories::product_id, categories::all_columns))
+                .order((product_categories::product_id, categories::id))
+                .load(conn)
+                .await?;
+            categories = Some(group_by_product(rows));
+        }
+
+        let mut suppliers = None;
+        if includes.suppliers {
+            let rows: Vec<(i32, Supplier)> = suppliers::table
+                .inner_join(product_suppliers::table)
+                .filter(product_suppliers::product_id.eq_any(&ids))
+                .select((product_suppliers::product_id, suppliers::all_columns))
+                .order((product_suppliers::product_id, suppliers::id))
+                .load(conn)
+                .await?;
+            suppliers = Some(group_by_product(rows));
         }
+
+        Ok(products
+            .into_iter()
+            .map(|product| ProductWithRelations {
+                brand: brands.as_ref().map(|brands| {
+                    product
+                        .brand_id
+                        .and_then(|brand_id| brands.get(&brand_id).cloned())
+                }),
+                categories: categories
+                    .as_mut()
+                    .map(|categories| categories.remove(&product.id).unwrap_or_default()),
+                suppliers: suppliers
+                    .as_mut()
+                    .map(|suppliers| suppliers.remove(&product.id).unwrap_or_default()),
+                product,
+            })
+            .collect())
+    }
+}
+
+/// Collects `(product_id, record)` rows into each product's records.
+fn group_by_product<T>(rows: Vec<(i32, T)>) -> HashMap<i32, Vec<T>> {
+    let mut grouped: HashMap<i32, Vec<T>> = HashMap::new();
+    for (product_id, record) in rows {
+        grouped.entry(product_id).or_default().push(record);
     }
+    grouped
 }
 
 #[derive(Default)]
@@ -653,72 +1650,140 @@ impl CategoryBuilder {
         Self { name }
     }
 
-    pub async fn build(self, conn: &mut AsyncPgConnection) -> i32 {
-        let category_id = crate::schema::categories::dsl::categories
-            .select(crate::schema::categories::dsl::id)
-            .load::<i32>(conn)
-            .await
-            .unwrap()
-            .into_iter()
-            .max()
-            .unwrap_or(0)
-            + 1;
-        let row = Category {
-            id: category_id,
-            name: self.name,
-            products: Vec::new(),
-        };
-        diesel::insert_into(crate::schema::categories::dsl::categories)
-            .values(row)
-            .execute(conn)
-            .await
-            .unwrap();
-        category_id
+    pub async fn build(self, conn: &mut AsyncPgConnection, actor: i32) -> QueryResult<i32> {
+        let row: Category = diesel::insert_into(crate::schema::categories::dsl::categories)
+            .values(NewCategoryRow { name: self.name })
+            .get_result(conn)
+            .await?;
+        AuditEntry::record(conn, actor, "category", row.id, None, Some(&row)).await?;
+        Ok(row.id)
     }
 }
 
 impl Category {
-    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> Self {
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
         crate::schema::categories::dsl::categories
             .filter(crate::schema::categories::dsl::id.eq(id))
             .first(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn update(self, conn: &mut AsyncPgConnection) {
-        diesel::update(
+    /// Returns `None` without writing anything if the category changed since
+    /// `self.updated_at`.
+    pub async fn update(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = categories::table
+            .find(self.id)
+            .for_update()
+            .first(conn)
+            .await?;
+        let after: Option<Self> = diesel::update(
             crate::schema::categories::dsl::categories
-                .filter(crate::schema::categories::dsl::id.eq(self.id)),
+                .filter(crate::schema::categories::dsl::id.eq(self.id))
+                .filter(crate::schema::categories::dsl::updated_at.eq(self.updated_at)),
         )
-        .set((
-            crate::schema::categories::dsl::name.eq(self.name),
-            crate::schema::categories::dsl::products.eq(self.products),
-        ))
-        .execute(conn)
+        .set(crate::schema::categories::dsl::name.eq(&self.name))
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(
+            conn,
+            actor,
+            "category",
+            self.id,
+            Some(&before),
+            Some(&after),
+        )
+        .await?;
+        Ok(Some(after))
     }
 
-    pub async fn delete(conn: &mut AsyncPgConnection, id: i32) {
+    /// Writes only the fields set in `changes`. Given a `version`, returns
+    /// `None` without writing anything if the category changed since then.
+    pub async fn patch(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+        version: Option<NaiveDateTime>,
+        changes: CategoryChanges,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = categories::table.find(id).for_update().first(conn).await?;
+        // Setting `updated_at` as well keeps an empty changeset from being an error
+        let after: Option<Self> = diesel::update(
+            categories::table
+                .find(id)
+                .filter(categories::updated_at.eq(version.unwrap_or(before.updated_at))),
+        )
+        .set((&changes, categories::updated_at.eq(diesel::dsl::now)))
+        .get_result(conn)
+        .await
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(conn, actor, "category", id, Some(&before), Some(&after)).await?;
+        Ok(Some(after))
+    }
+
+    pub async fn delete(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        let before = Category::get(conn, id).await?;
         diesel::delete(
             crate::schema::categories::dsl::categories
                 .filter(crate::schema::categories::dsl::id.eq(id)),
         )
         .execute(conn)
-        .await
-        .unwrap();
+        .await?;
+        AuditEntry::record(conn, actor, "category", id, Some(&before), None).await
     }
 
-    pub async fn get_all(conn: &mut AsyncPgConnection) -> Vec<Self> {
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
         crate::schema::categories::dsl::categories
             .load(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn get_names(conn: &mut AsyncPgConnection) -> Vec<(String, i32)> {
-        crate::schema::categories::dsl::categories.load(conn).await.unwrap().into_iter().map(|category: Category| (category.name, category.id)).collect()
+    fn filtered(filter: &CategoryFilter) -> categories::BoxedQuery<'static, Pg> {
+        let mut query = categories::table.into_boxed();
+        if let Some(name) = &filter.name {
+            query = query.filter(categories::name.ilike(contains_pattern(name)));
+        }
+        query
+    }
+
+    /// Loads up to `limit` categories matching `filter`, starting after the row
+    /// `after` points at.
+    pub async fn search(
+        conn: &mut AsyncPgConnection,
+        filter: &CategoryFilter,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        let direction = filter.direction.unwrap_or_default();
+        let query = Self::filtered(filter);
+        let query = match filter.sort.unwrap_or_default() {
+            CategoryColumn::Id => sort_by!(query, direction, after, categories::id: i32),
+            CategoryColumn::Name => sort_by!(query, direction, after, categories::name: String),
+            CategoryColumn::UpdatedAt => {
+                sort_by!(query, direction, after, categories::updated_at: NaiveDateTime)
+            }
+        };
+        query.limit(limit).load(conn).await
+    }
+
+    pub async fn count(conn: &mut AsyncPgConnection, filter: &CategoryFilter) -> QueryResult<i64> {
+        Self::filtered(filter).count().get_result(conn).await
+    }
+
+    pub async fn get_names(conn: &mut AsyncPgConnection) -> QueryResult<Vec<(String, i32)>> {
+        Ok(crate::schema::categories::dsl::categories.load(conn).await?.into_iter().map(|category: Category| (category.name, category.id)).collect())
     }
 
 }
@@ -749,77 +1814,174 @@ impl SupplierBuilder {
         self
     }
 
-    pub async fn build(self, conn: &mut AsyncPgConnection) -> i32 {
-        let supplier_id = crate::schema::suppliers::dsl::suppliers
-            .select(crate::schema::suppliers::dsl::id)
-            .load::<i32>(conn)
-            .await
-            .unwrap()
-            .into_iter()
-            .max()
-            .unwrap_or(0)
-            + 1;
-        let row = Supplier {
-            id: supplier_id,
-            name: self.name,
-            phone_number: self.phone_number,
-            products: Vec::new(),
-            email: self.email,
-        };
-        diesel::insert_into(crate::schema::suppliers::dsl::suppliers)
-            .values(row)
-            .execute(conn)
-            .await
-            .unwrap();
-        supplier_id
+    pub async fn build(self, conn: &mut AsyncPgConnection, actor: i32) -> QueryResult<i32> {
+        let row: Supplier = diesel::insert_into(crate::schema::suppliers::dsl::suppliers)
+            .values(NewSupplierRow {
+                name: self.name,
+                phone_number: self.phone_number,
+                email: self.email,
+            })
+            .get_result(conn)
+            .await?;
+        AuditEntry::record(conn, actor, "supplier", row.id, None, Some(&row)).await?;
+        Ok(row.id)
     }
 }
 
 impl Supplier {
-    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> Self {
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
         crate::schema::suppliers::dsl::suppliers
             .filter(crate::schema::suppliers::dsl::id.eq(id))
             .first(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn update(self, conn: &mut AsyncPgConnection) {
-        diesel::update(
+    /// Returns `None` without writing anything if the supplier changed since
+    /// `self.updated_at`.
+    pub async fn update(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = suppliers::table
+            .find(self.id)
+            .for_update()
+            .first(conn)
+            .await?;
+        let after: Option<Self> = diesel::update(
             crate::schema::suppliers::dsl::suppliers
-                .filter(crate::schema::suppliers::dsl::id.eq(self.id)),
+                .filter(crate::schema::suppliers::dsl::id.eq(self.id))
+                .filter(crate::schema::suppliers::dsl::updated_at.eq(self.updated_at)),
         )
         .set((
-            crate::schema::suppliers::dsl::name.eq(self.name),
-            crate::schema::suppliers::dsl::email.eq(self.email),
-            crate::schema::suppliers::dsl::phone_number.eq(self.phone_number),
-            crate::schema::suppliers::dsl::products.eq(self.products),
+            crate::schema::suppliers::dsl::name.eq(&self.name),
+            crate::schema::suppliers::dsl::email.eq(&self.email),
+            crate::schema::suppliers::dsl::phone_number.eq(&self.phone_number),
         ))
-        .execute(conn)
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(
+            conn,
+            actor,
+            "supplier",
+            self.id,
+            Some(&before),
+            Some(&after),
+        )
+        .await?;
+        Ok(Some(after))
+    }
+
+    /// Writes only the fields set in `changes`. Given a `version`, returns
+    /// `None` without writing anything if the supplier changed since then.
+    pub async fn patch(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+        version: Option<NaiveDateTime>,
+        changes: SupplierChanges,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = suppliers::table.find(id).for_update().first(conn).await?;
+        // Setting `updated_at` as well keeps an empty changeset from being an error
+        let after: Option<Self> = diesel::update(
+            suppliers::table
+                .find(id)
+                .filter(suppliers::updated_at.eq(version.unwrap_or(before.updated_at))),
+        )
+        .set((&changes, suppliers::updated_at.eq(diesel::dsl::now)))
+        .get_result(conn)
+        .await
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(conn, actor, "supplier", id, Some(&before), Some(&after)).await?;
+        Ok(Some(after))
     }
 
-    pub async fn delete(conn: &mut AsyncPgConnection, id: i32) {
+    pub async fn delete(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        let before = Supplier::get(conn, id).await?;
         diesel::delete(
             crate::schema::suppliers::dsl::suppliers
                 .filter(crate::schema::suppliers::dsl::id.eq(id)),
         )
         .execute(conn)
-        .await
-        .unwrap();
+        .await?;
+        AuditEntry::record(conn, actor, "supplier", id, Some(&before), None).await
     }
 
-    pub async fn get_all(conn: &mut AsyncPgConnection) -> Vec<Self> {
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
         crate::schema::suppliers::dsl::suppliers
             .load(conn)
             .await
-            .unwrap()
     }
-    pub async fn get_names(conn: &mut AsyncPgConnection) -> Vec<(String, i32)> {
-        crate::schema::suppliers::dsl::suppliers.load(conn).await.unwrap().into_iter().map(|supplier: Supplier| (supplier.name, supplier.id)).collect()
+
+    fn filtered(filter: &SupplierFilter) -> suppliers::BoxedQuery<'static, Pg> {
+        let mut query = suppliers::table.into_boxed();
+        if let Some(name) = &filter.name {
+            query = query.filter(suppliers::name.ilike(contains_pattern(name)));
+        }
+        query
+    }
+
+    /// Loads up to `limit` suppliers matching `filter`, starting after the row
+    /// `after` points at.
+    pub async fn search(
+        conn: &mut AsyncPgConnection,
+        filter: &SupplierFilter,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        let direction = filter.direction.unwrap_or_default();
+        let query = Self::filtered(filter);
+        let query = match filter.sort.unwrap_or_default() {
+            SupplierColumn::Id => sort_by!(query, direction, after, suppliers::id: i32),
+            SupplierColumn::Name => sort_by!(query, direction, after, suppliers::name: String),
+            SupplierColumn::PhoneNumber => {
+                sort_by_nullable!(query, direction, after, suppliers::phone_number: String)
+            }
+            SupplierColumn::Email => {
+                sort_by_nullable!(query, direction, after, suppliers::email: String)
+            }
+            SupplierColumn::UpdatedAt => {
+                sort_by!(query, direction, after, suppliers::updated_at: NaiveDateTime)
+            }
+        };
+        query.limit(limit).load(conn).await
+    }
+
+    pub async fn count(conn: &mut AsyncPgConnection, filter: &SupplierFilter) -> QueryResult<i64> {
+        Self::filtered(filter).count().get_result(conn).await
+    }
+
+    pub async fn get_names(conn: &mut AsyncPgConnection) -> QueryResult<Vec<(String, i32)>> {
+        Ok(crate::schema::suppliers::dsl::suppliers.load(conn).await?.into_iter().map(|supplier: Supplier| (supplier.name, supplier.id)).collect())
     }
 
+    /// Suppliers named like `text`, most similar first.
+    pub async fn search_similar(
+        conn: &mut AsyncPgConnection,
+        text: &str,
+        limit: i64,
+    ) -> QueryResult<Vec<Similar<Self>>> {
+        let rows: Vec<(Self, f32)> = suppliers::table
+            .filter(word_similarity(text, suppliers::name).ge(WORD_SIMILARITY))
+            .select((
+                suppliers::all_columns,
+                word_similarity(text, suppliers::name),
+            ))
+            .order((word_similarity(text, suppliers::name).desc(), suppliers::id))
+            .limit(limit)
+            .load(conn)
+            .await?;
+        Ok(rows.into_iter().map(Similar::from).collect())
+    }
 }
 
 #[derive(Default)]
@@ -832,71 +1994,113 @@ impl BrandBuilder {
         Self { name }
     }
 
-    pub async fn build(self, conn: &mut AsyncPgConnection) -> i32 {
-        let brand_id = crate::schema::brands::dsl::brands
-            .select(crate::schema::brands::dsl::id)
-            .load::<i32>(conn)
-            .await
-            .unwrap()
-            .into_iter()
-            .max()
-            .unwrap_or(0)
-            + 1;
-        let row = Brand {
-            id: brand_id,
-            name: self.name,
-            products: Vec::new(),
-        };
-        diesel::insert_into(crate::schema::brands::dsl::brands)
-            .values(row)
-            .execute(conn)
-            .await
-            .unwrap();
-        brand_id
+    pub async fn build(self, conn: &mut AsyncPgConnection, actor: i32) -> QueryResult<i32> {
+        let row: Brand = diesel::insert_into(crate::schema::brands::dsl::brands)
+            .values(NewBrandRow { name: self.name })
+            .get_result(conn)
+            .await?;
+        AuditEntry::record(conn, actor, "brand", row.id, None, Some(&row)).await?;
+        Ok(row.id)
     }
 }
 
 impl Brand {
-    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> Self {
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
         crate::schema::brands::dsl::brands
             .filter(crate::schema::brands::dsl::id.eq(id))
             .first(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn update(self, conn: &mut AsyncPgConnection) {
-        diesel::update(
-            crate::schema::brands::dsl::brands.filter(crate::schema::brands::dsl::id.eq(self.id)),
+    /// Returns `None` without writing anything if the brand changed since
+    /// `self.updated_at`.
+    pub async fn update(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = brands::table.find(self.id).for_update().first(conn).await?;
+        let after: Option<Self> = diesel::update(
+            crate::schema::brands::dsl::brands
+                .filter(crate::schema::brands::dsl::id.eq(self.id))
+                .filter(crate::schema::brands::dsl::updated_at.eq(self.updated_at)),
+        )
+        .set(crate::schema::brands::dsl::name.eq(&self.name))
+        .get_result(conn)
+        .await
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(conn, actor, "brand", self.id, Some(&before), Some(&after)).await?;
+        Ok(Some(after))
+    }
+
+    /// Writes only the fields set in `changes`. Given a `version`, returns
+    /// `None` without writing anything if the brand changed since then.
+    pub async fn patch(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+        version: Option<NaiveDateTime>,
+        changes: BrandChanges,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = brands::table.find(id).for_update().first(conn).await?;
+        // Setting `updated_at` as well keeps an empty changeset from being an error
+        let after: Option<Self> = diesel::update(
+            brands::table
+                .find(id)
+                .filter(brands::updated_at.eq(version.unwrap_or(before.updated_at))),
         )
-        .set((
-            crate::schema::brands::dsl::name.eq(self.name),
-            crate::schema::brands::dsl::products.eq(self.products),
-        ))
-        .execute(conn)
+        .set((&changes, brands::updated_at.eq(diesel::dsl::now)))
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(conn, actor, "brand", id, Some(&before), Some(&after)).await?;
+        Ok(Some(after))
     }
 
-    pub async fn delete(conn: &mut AsyncPgConnection, id: i32) {
+    pub async fn delete(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        let before = Brand::get(conn, id).await?;
         diesel::delete(
             crate::schema::brands::dsl::brands.filter(crate::schema::brands::dsl::id.eq(id)),
         )
         .execute(conn)
-        .await
-        .unwrap();
+        .await?;
+        AuditEntry::record(conn, actor, "brand", id, Some(&before), None).await
     }
 
-    pub async fn get_all(conn: &mut AsyncPgConnection) -> Vec<Self> {
-        crate::schema::brands::dsl::brands.load(conn).await.unwrap()
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        crate::schema::brands::dsl::brands.load(conn).await
     }
-    pub async fn get_names(conn: &mut AsyncPgConnection) -> Vec<(String, i32)> {
-        crate::schema::brands::dsl::brands.load(conn).await.unwrap().into_iter().map(|brand: Brand| (brand.name, brand.id)).collect()
+    pub async fn get_names(conn: &mut AsyncPgConnection) -> QueryResult<Vec<(String, i32)>> {
+        Ok(crate::schema::brands::dsl::brands.load(conn).await?.into_iter().map(|brand: Brand| (brand.name, brand.id)).collect())
     }
-}
 
-#[derive(Default)]
-pub struct PendingOrderBuilder {
+    /// Brands named like `text`, most similar first.
+    pub async fn search_similar(
+        conn: &mut AsyncPgConnection,
+        text: &str,
+        limit: i64,
+    ) -> QueryResult<Vec<Similar<Self>>> {
+        let rows: Vec<(Self, f32)> = brands::table
+            .filter(word_similarity(text, brands::name).ge(WORD_SIMILARITY))
+            .select((brands::all_columns, word_similarity(text, brands::name)))
+            .order((word_similarity(text, brands::name).desc(), brands::id))
+            .limit(limit)
+            .load(conn)
+            .await?;
+        Ok(rows.into_iter().map(Similar::from).collect())
+    }
+}
+
+#[derive(Default)]
+pub struct PendingOrderBuilder {
     pub product_id: i32,
     pub amount: f64,
 }
@@ -906,119 +2110,293 @@ impl PendingOrderBuilder {
         Self { product_id, amount }
     }
 
-    pub async fn build(self, conn: &mut AsyncPgConnection) -> i32 {
-        let order_id = crate::schema::pending_orders::dsl::pending_orders
-            .select(crate::schema::pending_orders::dsl::id)
-            .load::<i32>(conn)
-            .await
-            .unwrap()
-            .into_iter()
-            .max()
-            .unwrap_or(0)
-            + 1;
-        let row = PendingOrder {
-            id: order_id,
-            product_id: self.product_id,
-            amount: self.amount,
-        };
-        diesel::insert_into(crate::schema::pending_orders::dsl::pending_orders)
-            .values(row)
-            .execute(conn)
-            .await
-            .unwrap();
-        order_id
+    pub async fn build(self, conn: &mut AsyncPgConnection, actor: i32) -> QueryResult<i32> {
+        let row: PendingOrder =
+            diesel::insert_into(crate::schema::pending_orders::dsl::pending_orders)
+                .values(NewPendingOrderRow {
+                    product_id: self.product_id,
+                    amount: self.amount,
+                })
+                .get_result(conn)
+                .await?;
+        AuditEntry::record(conn, actor, "pending_order", row.id, None, Some(&row)).await?;
+        Ok(row.id)
     }
 }
 
 impl PendingOrder {
-    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> Self {
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
         crate::schema::pending_orders::dsl::pending_orders
             .filter(crate::schema::pending_orders::dsl::id.eq(id))
             .first(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn update(self, conn: &mut AsyncPgConnection) {
-        diesel::update(
+    /// Returns `None` without writing anything if the order changed since
+    /// `self.updated_at`.
+    pub async fn update(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = pending_orders::table
+            .find(self.id)
+            .for_update()
+            .first(conn)
+            .await?;
+        let after: Option<Self> = diesel::update(
             crate::schema::pending_orders::dsl::pending_orders
-                .filter(crate::schema::pending_orders::dsl::id.eq(self.id)),
+                .filter(crate::schema::pending_orders::dsl::id.eq(self.id))
+                .filter(crate::schema::pending_orders::dsl::updated_at.eq(self.updated_at)),
         )
         .set((
             crate::schema::pending_orders::dsl::product_id.eq(self.product_id),
             crate::schema::pending_orders::dsl::amount.eq(self.amount),
         ))
-        .execute(conn)
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(
+            conn,
+            actor,
+            "pending_order",
+            self.id,
+            Some(&before),
+            Some(&after),
+        )
+        .await?;
+        Ok(Some(after))
     }
 
-    pub async fn delete(conn: &mut AsyncPgConnection, id: i32) {
-        diesel::delete(
-            crate::schema::pending_orders::dsl::pending_orders
-                .filter(crate::schema::pending_orders::dsl::id.eq(id)),
+    /// Writes only the fields set in `changes`. Given a `version`, returns
+    /// `None` without writing anything if the order changed since then.
+    pub async fn patch(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+        version: Option<NaiveDateTime>,
+        changes: PendingOrderChanges,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = pending_orders::table
+            .find(id)
+            .for_update()
+            .first(conn)
+            .await?;
+        // Setting `updated_at` as well keeps an empty changeset from being an error
+        let after: Option<Self> = diesel::update(
+            pending_orders::table
+                .find(id)
+                .filter(pending_orders::updated_at.eq(version.unwrap_or(before.updated_at))),
         )
-        .execute(conn)
+        .set((&changes, pending_orders::updated_at.eq(diesel::dsl::now)))
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(
+            conn,
+            actor,
+            "pending_order",
+            id,
+            Some(&before),
+            Some(&after),
+        )
+        .await?;
+        Ok(Some(after))
     }
 
-    pub async fn get_all(conn: &mut AsyncPgConnection, limit: i64, offset: i64) -> Vec<Self> {
-        crate::schema::pending_orders::dsl::pending_orders
-            .limit(limit)
-            .offset(offset)
-            .load(conn)
-            .await
-            .unwrap()
+    pub async fn delete(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        Self::take(conn, actor, id).await?;
+        Ok(())
+    }
+
+    /// Deletes the order and returns it as it was. The row stays locked until
+    /// the transaction ends, so of two requests taking the same order the second
+    /// gets `NotFound`.
+    async fn take(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<Self> {
+        let before: Self = pending_orders::table
+            .find(id)
+            .for_update()
+            .first(conn)
+            .await?;
+        let deleted = diesel::delete(pending_orders::table.find(id))
+            .execute(conn)
+            .await?;
+        if deleted != 1 {
+            return Err(diesel::result::Error::NotFound);
+        }
+        AuditEntry::record(conn, actor, "pending_order", id, Some(&before), None).await?;
+        Ok(before)
+    }
+
+    fn filtered(filter: &PendingOrderFilter) -> pending_orders::BoxedQuery<'static, Pg> {
+        let mut query = pending_orders::table.into_boxed();
+        if let Some(product) = filter.product {
+            query = query.filter(pending_orders::product_id.eq(product));
+        }
+        query
+    }
+
+    /// Loads up to `limit` pending orders matching `filter`, starting after the row
+    /// `after` points at.
+    pub async fn search(
+        conn: &mut AsyncPgConnection,
+        filter: &PendingOrderFilter,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        let direction = filter.direction.unwrap_or_default();
+        let query = Self::filtered(filter);
+        let query = match filter.sort.unwrap_or_default() {
+            PendingOrderColumn::Id => sort_by!(query, direction, after, pending_orders::id: i32),
+            PendingOrderColumn::ProductId => {
+                sort_by!(query, direction, after, pending_orders::product_id: i32)
+            }
+            PendingOrderColumn::Amount => {
+                sort_by!(query, direction, after, pending_orders::amount: f64)
+            }
+            PendingOrderColumn::UpdatedAt => {
+                sort_by!(query, direction, after, pending_orders::updated_at: NaiveDateTime)
+            }
+        };
+        query.limit(limit).load(conn).await
+    }
+
+    pub async fn count(
+        conn: &mut AsyncPgConnection,
+        filter: &PendingOrderFilter,
+    ) -> QueryResult<i64> {
+        Self::filtered(filter).count().get_result(conn).await
     }
 
     pub async fn mark_as_received(
-        self,
         conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
         date: NaiveDateTime,
         actually_received: f64,
         damaged: f64,
-    ) -> ReceivedOrder {
-        let order_id = crate::schema::received_orders::dsl::received_orders
-            .select(crate::schema::received_orders::dsl::id)
-            .load::<i32>(conn)
-            .await
-            .unwrap()
-            .into_iter()
-            .max()
-            .unwrap_or(0)
-            + 1;
-        let row = ReceivedOrder {
-            id: order_id,
-            received: Some(date),
-            product_id: self.product_id,
-            gross_amount: self.amount,
-            actually_received,
-            damaged,
-        };
-        diesel::insert_into(crate::schema::received_orders::dsl::received_orders)
-            .values(row.clone())
-            .execute(conn)
-            .await
-            .unwrap();
-        Self::delete(conn, self.id).await;
-        row
+    ) -> QueryResult<ReceivedOrder> {
+        let order = Self::take(conn, actor, id).await?;
+        let row: ReceivedOrder =
+            diesel::insert_into(crate::schema::received_orders::dsl::received_orders)
+                .values(NewReceivedOrderRow {
+                    received: Some(date),
+                    product_id: order.product_id,
+                    gross_amount: order.amount,
+                    actually_received,
+                    damaged,
+                })
+                .get_result(conn)
+                .await?;
+        AuditEntry::record(conn, actor, "received_order", row.id, None, Some(&row)).await?;
+        row.record_stock(conn, actor, 1.0).await?;
+        Ok(row)
     }
 }
 
 impl ReceivedOrder {
-    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> Self {
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
         crate::schema::received_orders::dsl::received_orders
             .filter(crate::schema::received_orders::dsl::id.eq(id))
             .first(conn)
             .await
-            .unwrap()
     }
 
-    pub async fn update(self, conn: &mut AsyncPgConnection) {
-        diesel::update(
+    /// What receiving this order did to the product's stock. Together these
+    /// add `actually_received - damaged`.
+    fn stock_movements(&self) -> [(StockMovementReason, f64); 2] {
+        [
+            (StockMovementReason::Receipt, self.actually_received),
+            (StockMovementReason::Damage, -self.damaged),
+        ]
+    }
+
+    /// Records this order's stock movements, or reverses them when `sign` is -1.
+    async fn record_stock(
+        &self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        sign: f64,
+    ) -> QueryResult<()> {
+        let movements = self
+            .stock_movements()
+            .into_iter()
+            .map(|(reason, quantity)| (reason, sign * quantity))
+            .collect();
+        self.record_movements(conn, actor, movements).await
+    }
+
+    /// Records `movements` of this order's product. Increases go first, as stock
+    /// can't dip below zero even part way through.
+    async fn record_movements(
+        &self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        mut movements: Vec<(StockMovementReason, f64)>,
+    ) -> QueryResult<()> {
+        movements.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        for (reason, quantity) in movements {
+            if quantity != 0.0 {
+                StockMovement::record(
+                    conn,
+                    actor,
+                    self.product_id,
+                    quantity,
+                    reason,
+                    Some(self.id),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves stock to match this order after it was edited from `before`.
+    async fn restock(
+        &self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        before: &Self,
+    ) -> QueryResult<()> {
+        if before.product_id == self.product_id {
+            let changes = before
+                .stock_movements()
+                .into_iter()
+                .zip(self.stock_movements())
+                .map(|((reason, old), (_, new))| (reason, new - old))
+                .collect();
+            self.record_movements(conn, actor, changes).await?;
+        } else {
+            before.record_stock(conn, actor, -1.0).await?;
+            self.record_stock(conn, actor, 1.0).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns `None` without writing anything if the order changed since
+    /// `self.updated_at`.
+    pub async fn update(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = received_orders::table
+            .find(self.id)
+            .for_update()
+            .first(conn)
+            .await?;
+        let after: Option<Self> = diesel::update(
             crate::schema::received_orders::dsl::received_orders
-                .filter(crate::schema::received_orders::dsl::id.eq(self.id)),
+                .filter(crate::schema::received_orders::dsl::id.eq(self.id))
+                .filter(crate::schema::received_orders::dsl::updated_at.eq(self.updated_at)),
         )
         .set((
             crate::schema::received_orders::dsl::product_id.eq(self.product_id),
@@ -1027,27 +2405,1225 @@ impl ReceivedOrder {
             crate::schema::received_orders::dsl::gross_amount.eq(self.gross_amount),
             crate::schema::received_orders::dsl::received.eq(self.received),
         ))
-        .execute(conn)
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(
+            conn,
+            actor,
+            "received_order",
+            self.id,
+            Some(&before),
+            Some(&after),
+        )
+        .await?;
+        after.restock(conn, actor, &before).await?;
+        Ok(Some(after))
     }
 
-    pub async fn delete(conn: &mut AsyncPgConnection, id: i32) {
+    /// Writes only the fields set in `changes`. Given a `version`, returns
+    /// `None` without writing anything if the order changed since then.
+    pub async fn patch(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+        version: Option<NaiveDateTime>,
+        changes: ReceivedOrderChanges,
+    ) -> QueryResult<Option<Self>> {
+        let before: Self = received_orders::table
+            .find(id)
+            .for_update()
+            .first(conn)
+            .await?;
+        // Setting `updated_at` as well keeps an empty changeset from being an error
+        let after: Option<Self> = diesel::update(
+            received_orders::table
+                .find(id)
+                .filter(received_orders::updated_at.eq(version.unwrap_or(before.updated_at))),
+        )
+        .set((&changes, received_orders::updated_at.eq(diesel::dsl::now)))
+        .get_result(conn)
+        .await
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(
+            conn,
+            actor,
+            "received_order",
+            id,
+            Some(&before),
+            Some(&after),
+        )
+        .await?;
+        after.restock(conn, actor, &before).await?;
+        Ok(Some(after))
+    }
+
+    /// Deletes the order and takes what it added back out of stock.
+    pub async fn delete(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        Self::take(conn, actor, id).await?;
+        Ok(())
+    }
+
+    /// Deletes the order, takes what it added back out of stock and returns it
+    /// as it was. Like `PendingOrder::take`, the row stays locked so that it is
+    /// only ever taken once.
+    async fn take(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<Self> {
+        let before: Self = received_orders::table
+            .find(id)
+            .for_update()
+            .first(conn)
+            .await?;
+        let deleted = diesel::delete(received_orders::table.find(id))
+            .execute(conn)
+            .await?;
+        if deleted != 1 {
+            return Err(diesel::result::Error::NotFound);
+        }
+        AuditEntry::record(conn, actor, "received_order", id, Some(&before), None).await?;
+        before.record_stock(conn, actor, -1.0).await?;
+        Ok(before)
+    }
+
+    /// Puts the order back into the pending orders, returning the new pending
+    /// order's id.
+    pub async fn mark_as_pending(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+    ) -> QueryResult<i32> {
+        let order = Self::take(conn, actor, id).await?;
+        PendingOrderBuilder::new(order.product_id, order.gross_amount)
+            .build(conn, actor)
+            .await
+    }
+
+    fn filtered(filter: &ReceivedOrderFilter) -> received_orders::BoxedQuery<'static, Pg> {
+        let mut query = received_orders::table.into_boxed();
+        if let Some(product) = filter.product {
+            query = query.filter(received_orders::product_id.eq(product));
+        }
+        if let Some(since) = filter
+            .since
+            .and_then(|since| NaiveDateTime::from_timestamp_opt(since, 0))
+        {
+            query = query.filter(received_orders::received.ge(since));
+        }
+        if let Some(until) = filter
+            .until
+            .and_then(|until| NaiveDateTime::from_timestamp_opt(until, 0))
+        {
+            query = query.filter(received_orders::received.lt(until));
+        }
+        query
+    }
+
+    /// Loads up to `limit` received orders matching `filter`, starting after the row
+    /// `after` points at.
+    pub async fn search(
+        conn: &mut AsyncPgConnection,
+        filter: &ReceivedOrderFilter,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        let direction = filter.direction.unwrap_or_default();
+        let query = Self::filtered(filter);
+        let query = match filter.sort.unwrap_or_default() {
+            ReceivedOrderColumn::Id => sort_by!(query, direction, after, received_orders::id: i32),
+            ReceivedOrderColumn::Received => {
+                sort_by_nullable!(query, direction, after, received_orders::received: NaiveDateTime)
+            }
+            ReceivedOrderColumn::ProductId => {
+                sort_by!(query, direction, after, received_orders::product_id: i32)
+            }
+            ReceivedOrderColumn::GrossAmount => {
+                sort_by!(query, direction, after, received_orders::gross_amount: f64)
+            }
+            ReceivedOrderColumn::ActuallyReceived => {
+                sort_by!(query, direction, after, received_orders::actually_received: f64)
+            }
+            ReceivedOrderColumn::Damaged => {
+                sort_by!(query, direction, after, received_orders::damaged: f64)
+            }
+            ReceivedOrderColumn::UpdatedAt => {
+                sort_by!(query, direction, after, received_orders::updated_at: NaiveDateTime)
+            }
+        };
+        query.limit(limit).load(conn).await
+    }
+
+    pub async fn count(
+        conn: &mut AsyncPgConnection,
+        filter: &ReceivedOrderFilter,
+    ) -> QueryResult<i64> {
+        Self::filtered(filter).count().get_result(conn).await
+    }
+}
+
+impl Role {
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
+        roles::table.find(id).first(conn).await
+    }
+
+    pub async fn from_name(conn: &mut AsyncPgConnection, name: &str) -> QueryResult<Self> {
+        roles::table.filter(roles::name.eq(name)).first(conn).await
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        roles::table.order(roles::name).load(conn).await
+    }
+
+    pub async fn create(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        name: &str,
+        permissions: &[String],
+    ) -> QueryResult<RoleWithPermissions> {
+        let role: Role = diesel::insert_into(roles::table)
+            .values(roles::name.eq(name))
+            .get_result(conn)
+            .await?;
+        Role::set_permissions(conn, role.id, permissions).await?;
+        let after = role.with_permissions(conn).await?;
+        AuditEntry::record(conn, actor, "role", after.role.id, None, Some(&after)).await?;
+        Ok(after)
+    }
+
+    /// Renames the role and replaces its permissions with `permissions`.
+    pub async fn update(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        permissions: &[String],
+    ) -> QueryResult<()> {
+        let before = roles::table
+            .find(self.id)
+            .for_update()
+            .first::<Role>(conn)
+            .await?
+            .with_permissions(conn)
+            .await?;
+        diesel::update(roles::table.find(self.id))
+            .set(roles::name.eq(&self.name))
+            .execute(conn)
+            .await?;
+        Role::set_permissions(conn, self.id, permissions).await?;
+        let after = self.with_permissions(conn).await?;
+        AuditEntry::record(
+            conn,
+            actor,
+            "role",
+            after.role.id,
+            Some(&before),
+            Some(&after),
+        )
+        .await
+    }
+
+    pub async fn delete(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        let before = Role::get(conn, id).await?.with_permissions(conn).await?;
+        diesel::delete(roles::table.find(id)).execute(conn).await?;
+        AuditEntry::record(conn, actor, "role", id, Some(&before), None).await
+    }
+
+    pub async fn with_permissions(
+        self,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<RoleWithPermissions> {
+        let permissions = self.get_permissions(conn).await?;
+        Ok(RoleWithPermissions {
+            role: self,
+            permissions,
+        })
+    }
+
+    pub async fn get_permissions(&self, conn: &mut AsyncPgConnection) -> QueryResult<Vec<String>> {
+        role_permissions::table
+            .filter(role_permissions::role_id.eq(self.id))
+            .select(role_permissions::permission)
+            .order(role_permissions::permission)
+            .load(conn)
+            .await
+    }
+
+    async fn set_permissions(
+        conn: &mut AsyncPgConnection,
+        role_id: i32,
+        permissions: &[String],
+    ) -> QueryResult<()> {
+        diesel::delete(role_permissions::table.filter(role_permissions::role_id.eq(role_id)))
+            .execute(conn)
+            .await?;
+        let rows = permissions
+            .iter()
+            .map(|permission| {
+                (
+                    role_permissions::role_id.eq(role_id),
+                    role_permissions::permission.eq(permission),
+                )
+            })
+            .collect::<Vec<_>>();
+        if !rows.is_empty() {
+            diesel::insert_into(role_permissions::table)
+                .values(rows)
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+impl Session {
+    /// Starts a session for `user_id`, returning the plaintext token. Only its
+    /// hash is stored, so the token can't be recovered from the database.
+    pub async fn create(
+        conn: &mut AsyncPgConnection,
+        user_id: i32,
+        length: Duration,
+    ) -> QueryResult<(String, Session)> {
+        let token = generate_token();
+
+        let now = Utc::now().naive_utc();
+        let row = Session {
+            token_hash: hash_token(&token),
+            user_id,
+            created: now,
+            expires: now + length,
+        };
+        diesel::insert_into(crate::schema::sessions::dsl::sessions)
+            .values(&row)
+            .execute(conn)
+            .await?;
+        Ok((token, row))
+    }
+
+    pub async fn from_token(
+        conn: &mut AsyncPgConnection,
+        token: &str,
+    ) -> QueryResult<Option<Self>> {
+        crate::schema::sessions::dsl::sessions
+            .filter(crate::schema::sessions::dsl::token_hash.eq(hash_token(token)))
+            .filter(crate::schema::sessions::dsl::expires.gt(Utc::now().naive_utc()))
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn delete(conn: &mut AsyncPgConnection, token_hash: &str) -> QueryResult<()> {
         diesel::delete(
-            crate::schema::received_orders::dsl::received_orders
-                .filter(crate::schema::received_orders::dsl::id.eq(id)),
+            crate::schema::sessions::dsl::sessions
+                .filter(crate::schema::sessions::dsl::token_hash.eq(token_hash)),
         )
         .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_expired(conn: &mut AsyncPgConnection, user_id: i32) -> QueryResult<()> {
+        diesel::delete(
+            crate::schema::sessions::dsl::sessions
+                .filter(crate::schema::sessions::dsl::user_id.eq(user_id))
+                .filter(crate::schema::sessions::dsl::expires.le(Utc::now().naive_utc())),
+        )
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+impl UserUpdate {
+    /// Returns `None` without writing anything if the user changed since
+    /// `self.updated_at`.
+    pub async fn update(
+        self,
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+    ) -> QueryResult<Option<User>> {
+        let before: User = users::table.find(self.id).for_update().first(conn).await?;
+        let after: Option<User> = diesel::update(
+            crate::schema::users::dsl::users
+                .filter(crate::schema::users::dsl::id.eq(self.id))
+                .filter(crate::schema::users::dsl::updated_at.eq(self.updated_at)),
+        )
+        .set((
+            crate::schema::users::dsl::name.eq(&self.name),
+            crate::schema::users::dsl::email.eq(&self.email),
+        ))
+        .get_result(conn)
         .await
-        .unwrap();
+        .optional()?;
+        let after = match after {
+            Some(after) => after,
+            None => return Ok(None),
+        };
+        AuditEntry::record(conn, actor, "user", self.id, Some(&before), Some(&after)).await?;
+        Ok(Some(after))
     }
+}
 
-    pub async fn get_all(conn: &mut AsyncPgConnection, limit: i64, offset: i64) -> Vec<Self> {
-        crate::schema::received_orders::dsl::received_orders
+impl ApiKey {
+    /// Creates a key and returns it together with its plaintext value, which is
+    /// not stored and can't be shown again.
+    pub async fn create(
+        conn: &mut AsyncPgConnection,
+        name: String,
+        created_by: i32,
+        scopes: Vec<String>,
+    ) -> QueryResult<(String, ApiKey)> {
+        let key = generate_token();
+        let row = NewApiKey {
+            name,
+            key_hash: hash_token(&key),
+            prefix: key.chars().take(8).collect(),
+            created_by,
+            scopes: scopes.into_iter().map(Some).collect(),
+        };
+        let api_key: ApiKey = diesel::insert_into(crate::schema::api_keys::dsl::api_keys)
+            .values(row)
+            .get_result(conn)
+            .await?;
+        AuditEntry::record(
+            conn,
+            created_by,
+            "api_key",
+            api_key.id,
+            None,
+            Some(&api_key),
+        )
+        .await?;
+        Ok((key, api_key))
+    }
+
+    pub async fn from_key(conn: &mut AsyncPgConnection, key: &str) -> QueryResult<Option<Self>> {
+        crate::schema::api_keys::dsl::api_keys
+            .filter(crate::schema::api_keys::dsl::key_hash.eq(hash_token(key)))
+            .filter(crate::schema::api_keys::dsl::revoked.is_null())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        crate::schema::api_keys::dsl::api_keys
+            .order(crate::schema::api_keys::dsl::id)
+            .load(conn)
+            .await
+    }
+
+    pub async fn revoke(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        let before: ApiKey = crate::schema::api_keys::dsl::api_keys
+            .find(id)
+            .first(conn)
+            .await?;
+        let after: Option<ApiKey> = diesel::update(
+            crate::schema::api_keys::dsl::api_keys
+                .filter(crate::schema::api_keys::dsl::id.eq(id))
+                .filter(crate::schema::api_keys::dsl::revoked.is_null()),
+        )
+        .set(crate::schema::api_keys::dsl::revoked.eq(Utc::now().naive_utc()))
+        .get_result(conn)
+        .await
+        .optional()?;
+        match after {
+            Some(after) => {
+                AuditEntry::record(conn, actor, "api_key", id, Some(&before), Some(&after)).await
+            }
+            // Already revoked
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes the keys `user_id` created, which can't outlive them, recording
+    /// each one in the audit log.
+    pub async fn delete_created_by(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        user_id: i32,
+    ) -> QueryResult<()> {
+        let deleted: Vec<ApiKey> = diesel::delete(
+            crate::schema::api_keys::dsl::api_keys
+                .filter(crate::schema::api_keys::dsl::created_by.eq(user_id)),
+        )
+        .get_results(conn)
+        .await?;
+        for api_key in deleted {
+            AuditEntry::record(conn, actor, "api_key", api_key.id, Some(&api_key), None).await?;
+        }
+        Ok(())
+    }
+
+    pub fn scopes(&self) -> Vec<String> {
+        self.scopes.iter().flatten().cloned().collect()
+    }
+}
+
+impl Webhook {
+    /// Registers `url` for `events`, signing deliveries with a new secret.
+    pub async fn create(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        url: String,
+        events: Vec<String>,
+    ) -> QueryResult<Self> {
+        let row = NewWebhook {
+            url,
+            secret: generate_token(),
+            events: events.into_iter().map(Some).collect(),
+            created_by: Some(actor),
+        };
+        let webhook: Webhook = diesel::insert_into(webhooks::table)
+            .values(row)
+            .get_result(conn)
+            .await?;
+        AuditEntry::record(conn, actor, "webhook", webhook.id, None, Some(&webhook)).await?;
+        Ok(webhook)
+    }
+
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
+        webhooks::table.find(id).first(conn).await
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        webhooks::table.order(webhooks::id).load(conn).await
+    }
+
+    /// Removes the webhook along with its delivery log.
+    pub async fn delete(conn: &mut AsyncPgConnection, actor: i32, id: i32) -> QueryResult<()> {
+        let before = Webhook::get(conn, id).await?;
+        diesel::delete(webhooks::table.find(id))
+            .execute(conn)
+            .await?;
+        AuditEntry::record(conn, actor, "webhook", id, Some(&before), None).await
+    }
+}
+
+impl WebhookDelivery {
+    /// Queues `payload` for every webhook subscribed to `event`.
+    pub async fn enqueue(
+        conn: &mut AsyncPgConnection,
+        event: &str,
+        payload: &serde_json::Value,
+    ) -> QueryResult<()> {
+        diesel::insert_into(webhook_deliveries::table)
+            .values(
+                webhooks::table
+                    .filter(webhooks::events.contains(vec![Some(event)]))
+                    .select((
+                        webhooks::id,
+                        event.into_sql::<diesel::sql_types::Text>(),
+                        payload.into_sql::<diesel::sql_types::Jsonb>(),
+                    )),
+            )
+            .into_columns((
+                webhook_deliveries::webhook_id,
+                webhook_deliveries::event,
+                webhook_deliveries::payload,
+            ))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Self> {
+        webhook_deliveries::table.find(id).first(conn).await
+    }
+
+    /// A webhook's deliveries, newest first.
+    pub async fn for_webhook(
+        conn: &mut AsyncPgConnection,
+        webhook_id: i32,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = webhook_deliveries::table
+            .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(webhook_deliveries::id.lt(after.id));
+        }
+        query
+            .order(webhook_deliveries::id.desc())
+            .limit(limit)
+            .load(conn)
+            .await
+    }
+
+    /// Takes up to `limit` deliveries that are due, pushing their next attempt
+    /// `lease` into the future so no other worker takes them meanwhile.
+    pub async fn claim_due(
+        conn: &mut AsyncPgConnection,
+        lease: Duration,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        diesel::sql_query(
+            "UPDATE webhook_deliveries SET next_attempt = NOW() + $1 * INTERVAL '1 second' \
+            WHERE id IN (SELECT id FROM webhook_deliveries WHERE next_attempt <= NOW() \
+                ORDER BY next_attempt LIMIT $2 FOR UPDATE SKIP LOCKED) \
+            RETURNING *",
+        )
+        .bind::<diesel::sql_types::Int8, _>(lease.num_seconds())
+        .bind::<diesel::sql_types::Int8, _>(limit)
+        .load(conn)
+        .await
+    }
+
+    /// Records the outcome of an attempt: the response status if there was one,
+    /// and an error unless it succeeded. `next_attempt` is when to retry.
+    pub async fn record_attempt(
+        conn: &mut AsyncPgConnection,
+        id: i32,
+        status: Option<i32>,
+        error: Option<String>,
+        next_attempt: Option<NaiveDateTime>,
+    ) -> QueryResult<Self> {
+        let delivered = error.is_none();
+        let attempt = (
+            webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+            webhook_deliveries::last_status.eq(status),
+            webhook_deliveries::last_error.eq(error),
+            webhook_deliveries::next_attempt.eq(next_attempt),
+        );
+        let query = diesel::update(webhook_deliveries::table.find(id));
+        if delivered {
+            query
+                .set((attempt, webhook_deliveries::delivered.eq(diesel::dsl::now)))
+                .get_result(conn)
+                .await
+        } else {
+            query.set(attempt).get_result(conn).await
+        }
+    }
+}
+
+impl IdempotentRequest {
+    /// Claims `key` for `request` unless it was used in the last `window`, in
+    /// which case that earlier request is returned. Waits for a request still
+    /// running with the same key to finish first.
+    pub async fn begin(
+        conn: &mut AsyncPgConnection,
+        user_id: i32,
+        key: &str,
+        request: &str,
+        window: Duration,
+    ) -> QueryResult<Option<Self>> {
+        diesel::delete(
+            idempotency_keys::table
+                .filter(idempotency_keys::created.lt(Utc::now().naive_utc() - window)),
+        )
+        .execute(conn)
+        .await?;
+        let claimed = diesel::insert_into(idempotency_keys::table)
+            .values((
+                idempotency_keys::user_id.eq(user_id),
+                idempotency_keys::key.eq(key),
+                idempotency_keys::request.eq(request),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+        if claimed == 1 {
+            return Ok(None);
+        }
+        idempotency_keys::table
+            .find((user_id, key))
+            .first(conn)
+            .await
+            .map(Some)
+    }
+
+    /// Stores the response to replay for `key`.
+    pub async fn complete(
+        conn: &mut AsyncPgConnection,
+        user_id: i32,
+        key: &str,
+        response: &serde_json::Value,
+    ) -> QueryResult<()> {
+        diesel::update(idempotency_keys::table.find((user_id, key)))
+            .set(idempotency_keys::response.eq(response))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+}
+
+impl StockMovement {
+    /// Records a change to a product's stock and applies it to the product's
+    /// `amount`, which always equals the sum of its movements.
+    pub async fn record(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        product_id: i32,
+        quantity: f64,
+        reason: StockMovementReason,
+        order_id: Option<i32>,
+    ) -> QueryResult<Self> {
+        // Locked so that `before`, and with it the audit entry and any webhook
+        // about crossing the buy level, see the amount this movement changes
+        let before: Product = products::table
+            .find(product_id)
+            .for_update()
+            .first(conn)
+            .await?;
+        let movement = diesel::insert_into(stock_movements::table)
+            .values((
+                stock_movements::product_id.eq(product_id),
+                stock_movements::quantity.eq(quantity),
+                stock_movements::reason.eq(reason),
+                stock_movements::order_id.eq(order_id),
+                stock_movements::user_id.eq(actor),
+            ))
+            .get_result(conn)
+            .await?;
+        let after: Product = diesel::update(
+            crate::schema::products::dsl::products
+                .filter(crate::schema::products::dsl::id.eq(product_id)),
+        )
+        .set(
+            crate::schema::products::dsl::amount
+                .eq(crate::schema::products::dsl::amount + quantity),
+        )
+        .get_result(conn)
+        .await?;
+        AuditEntry::record(
+            conn,
+            actor,
+            "product",
+            product_id,
+            Some(&before),
+            Some(&after),
+        )
+        .await?;
+        Ok(movement)
+    }
+}
+
+impl AuditEntry {
+    /// Records a change made by `actor`. `before` is `None` for inserts and
+    /// `after` is `None` for deletes.
+    pub async fn record<T: Serialize>(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        entity: &str,
+        entity_id: i32,
+        before: Option<&T>,
+        after: Option<&T>,
+    ) -> QueryResult<()> {
+        let action = match (before, after) {
+            (None, _) => AuditAction::Insert,
+            (Some(_), None) => AuditAction::Delete,
+            (Some(_), Some(_)) => AuditAction::Update,
+        };
+        let to_json = |value: Option<&T>| {
+            value
+                .map(serde_json::to_value)
+                .transpose()
+                .map_err(|error| diesel::result::Error::SerializationError(Box::new(error)))
+        };
+        let before = to_json(before)?;
+        let after = to_json(after)?;
+        diesel::insert_into(audit_log::table)
+            .values((
+                audit_log::user_id.eq(actor),
+                audit_log::entity.eq(entity),
+                audit_log::entity_id.eq(entity_id),
+                audit_log::action.eq(action),
+                audit_log::before.eq(&before),
+                audit_log::after.eq(&after),
+            ))
+            .execute(conn)
+            .await?;
+        // Queued in the same transaction, so webhooks only hear of changes that were kept
+        for (event, payload) in crate::webhooks::events(entity, before.as_ref(), after.as_ref()) {
+            WebhookDelivery::enqueue(conn, event, &payload).await?;
+        }
+        Ok(())
+    }
+
+    /// Newest entries first.
+    pub async fn search(
+        conn: &mut AsyncPgConnection,
+        filter: &AuditFilter,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = audit_log::table.into_boxed();
+        if let Some(after) = after {
+            query = query.filter(audit_log::id.lt(after.id));
+        }
+        if let Some(user_id) = filter.user_id {
+            query = query.filter(audit_log::user_id.eq(user_id));
+        }
+        if let Some(entity) = &filter.entity {
+            query = query.filter(audit_log::entity.eq(entity));
+        }
+        if let Some(entity_id) = filter.entity_id {
+            query = query.filter(audit_log::entity_id.eq(entity_id));
+        }
+        if let Some(action) = filter.action {
+            query = query.filter(audit_log::action.eq(action));
+        }
+        if let Some(since) = filter
+            .since
+            .and_then(|since| NaiveDateTime::from_timestamp_opt(since, 0))
+        {
+            query = query.filter(audit_log::occurred_at.ge(since));
+        }
+        if let Some(until) = filter
+            .until
+            .and_then(|until| NaiveDateTime::from_timestamp_opt(until, 0))
+        {
+            query = query.filter(audit_log::occurred_at.lt(until));
+        }
+        query
+            .order(audit_log::id.desc())
             .limit(limit)
-            .offset(offset)
             .load(conn)
             .await
+    }
+
+    /// Entries after `id`, oldest first.
+    pub async fn after(
+        conn: &mut AsyncPgConnection,
+        id: i32,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        audit_log::table
+            .filter(audit_log::id.gt(id))
+            .order(audit_log::id)
+            .limit(limit)
+            .load(conn)
+            .await
+    }
+
+    /// The oldest transaction still running and the first one that hasn't
+    /// started yet. Once the oldest running one is past an earlier first, every
+    /// entry written before then is either visible or rolled back.
+    pub async fn transaction_horizon(conn: &mut AsyncPgConnection) -> QueryResult<(i64, i64)> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::BigInt;
+
+        diesel::select((
+            sql::<BigInt>("pg_snapshot_xmin(pg_current_snapshot())::text::bigint"),
+            sql::<BigInt>("pg_snapshot_xmax(pg_current_snapshot())::text::bigint"),
+        ))
+        .get_result(conn)
+        .await
+    }
+
+    /// The id of the newest entry, or 0 when there are none.
+    pub async fn last_id(conn: &mut AsyncPgConnection) -> QueryResult<i32> {
+        let id: Option<i32> = audit_log::table
+            .select(diesel::dsl::max(audit_log::id))
+            .get_result(conn)
+            .await?;
+        Ok(id.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::database::{Manager, Pool};
+    use crate::error::{ApiError, ApiResult};
+    use crate::pagination::Page;
+    use crate::services;
+    use diesel_async::scoped_futures::ScopedFutureExt;
+    use rocket::form::{FromFormField, ValueField};
+    use rocket::futures::future::{join, try_join_all};
+    use rocket::tokio::sync::oneshot;
+    use rocket::tokio::time::sleep;
+    use std::time::Duration;
+
+    const PARALLEL_PRODUCTS: usize = 50;
+
+    /// A new user for tests to act as.
+    pub(crate) async fn test_actor(conn: &mut AsyncPgConnection) -> User {
+        diesel::insert_into(crate::schema::users::dsl::users)
+            .values(NewUserRow {
+                name: format!("test actor {}", generate_token()),
+                email: String::new(),
+                password: String::new(),
+            })
+            .get_result(conn)
+            .await
             .unwrap()
     }
+
+    /// Runs against the migrated database at `DATABASE_URL`, so it is skipped
+    /// unless asked for with `cargo test -- --ignored`.
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn products_created_in_parallel_get_distinct_ids() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let actor = test_actor(conn.as_mut()).await;
+        drop(conn);
+
+        let run = generate_token();
+        let creates = (0..PARALLEL_PRODUCTS).map(|n| {
+            let pool = &pool;
+            let run = &run;
+            async move {
+                services::transaction(pool, |conn| {
+                    async move {
+                        Ok(ProductBuilder::new(
+                            &format!("{} {}", run, n),
+                            "Parallel product",
+                            false,
+                            BigDecimal::from(1),
+                            BigDecimal::from(2),
+                        )
+                        .build(conn, actor.id)
+                        .await?)
+                    }
+                    .scope_boxed()
+                })
+                .await
+            }
+        });
+        let mut ids = try_join_all(creates).await.unwrap();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), PARALLEL_PRODUCTS);
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn paging_visits_every_product_once() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let conn = conn.as_mut();
+        let actor = test_actor(conn).await;
+        let category = CategoryBuilder::new(generate_token())
+            .build(conn, actor.id)
+            .await
+            .unwrap();
+        // Repeated names and missing buy levels make ties and nulls to page across
+        for (n, buy_level) in [Some(1.0), None, Some(2.0), None, Some(1.0)]
+            .iter()
+            .enumerate()
+        {
+            let mut builder = ProductBuilder::new(
+                &format!("paging {} {}", category, n),
+                ["a", "b"][n % 2],
+                false,
+                BigDecimal::from(1),
+                BigDecimal::from(2),
+            )
+            .with_categories(&[category]);
+            if let Some(buy_level) = buy_level {
+                builder = builder.with_buy_level(*buy_level);
+            }
+            builder.build(conn, actor.id).await.unwrap();
+        }
+
+        for sort in [ProductColumn::Name, ProductColumn::BuyLevel] {
+            for direction in [SortDirection::Asc, SortDirection::Desc] {
+                let filter = ProductFilter {
+                    category: Some(category),
+                    sort: Some(sort),
+                    direction: Some(direction),
+                    ..Default::default()
+                };
+                let all = Product::search(conn, &filter, None, 100).await.unwrap();
+                let mut paged = Vec::new();
+                let mut after = None;
+                loop {
+                    let rows = Product::search(conn, &filter, after.as_ref(), 3)
+                        .await
+                        .unwrap();
+                    let page = Page::new(rows, 2, sort);
+                    paged.extend(page.items);
+                    match page.next {
+                        Some(next) => {
+                            after = Some(Cursor::from_value(ValueField::from_value(&next)).unwrap())
+                        }
+                        None => break,
+                    }
+                }
+                assert_eq!(paged, all, "{:?} {:?}", sort, direction);
+            }
+        }
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn constraint_violations_become_api_errors() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let actor = test_actor(conn.as_mut()).await;
+        drop(conn);
+
+        let upc = generate_token();
+        let create = || {
+            services::transaction(&pool, |conn| {
+                let upc = &upc;
+                async move {
+                    Ok(ProductBuilder::new(
+                        upc,
+                        "Constrained",
+                        false,
+                        BigDecimal::from(1),
+                        BigDecimal::from(2),
+                    )
+                    .build(conn, actor.id)
+                    .await?)
+                }
+                .scope_boxed()
+            })
+        };
+        let product = create().await.unwrap();
+        assert!(matches!(create().await, Err(ApiError::Conflict(_))));
+
+        let sale = services::transaction(&pool, |conn| {
+            async move {
+                Ok(StockMovement::record(
+                    conn,
+                    actor.id,
+                    product,
+                    -1.0,
+                    StockMovementReason::Sale,
+                    None,
+                )
+                .await?)
+            }
+            .scope_boxed()
+        })
+        .await;
+        match sale {
+            Err(ApiError::InvalidFields(fields)) => assert!(fields.contains_key("amount")),
+            other => panic!("expected an invalid amount, got {:?}", other),
+        }
+
+        let order = services::transaction(&pool, |conn| {
+            async move {
+                Ok(PendingOrderBuilder::new(-1, 1.0)
+                    .build(conn, actor.id)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await;
+        match order {
+            Err(ApiError::InvalidFields(fields)) => assert!(fields.contains_key("product_id")),
+            other => panic!("expected an invalid product_id, got {:?}", other),
+        }
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn parallel_movements_audit_the_amount_they_changed() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let actor = test_actor(conn.as_mut()).await;
+        let product = ProductBuilder::new(
+            &generate_token(),
+            "Busy",
+            false,
+            BigDecimal::from(1),
+            BigDecimal::from(2),
+        )
+        .build(conn.as_mut(), actor.id)
+        .await
+        .unwrap();
+        drop(conn);
+
+        let movements = (0..PARALLEL_PRODUCTS).map(|_| {
+            services::transaction(&pool, |conn| {
+                async move {
+                    Ok(StockMovement::record(
+                        conn,
+                        actor.id,
+                        product,
+                        1.0,
+                        StockMovementReason::Adjustment,
+                        None,
+                    )
+                    .await?)
+                }
+                .scope_boxed()
+            })
+        });
+        try_join_all(movements).await.unwrap();
+
+        let mut conn = pool.get().await.unwrap();
+        let entries: Vec<AuditEntry> = audit_log::table
+            .filter(audit_log::entity.eq("product"))
+            .filter(audit_log::entity_id.eq(product))
+            .filter(audit_log::action.eq(AuditAction::Update))
+            .load(conn.as_mut())
+            .await
+            .unwrap();
+        let mut befores = entries
+            .iter()
+            .map(|entry| {
+                let amount = |value: &Option<serde_json::Value>| {
+                    value.as_ref().unwrap()["amount"].as_f64().unwrap()
+                };
+                assert_eq!(amount(&entry.before) + 1.0, amount(&entry.after));
+                amount(&entry.before)
+            })
+            .collect::<Vec<_>>();
+        befores.sort_by(f64::total_cmp);
+        let expected = (0..PARALLEL_PRODUCTS).map(|n| n as f64).collect::<Vec<_>>();
+        assert_eq!(befores, expected);
+    }
+
+    /// Receives pending order `id` if `receive`, or else deletes received order `id`.
+    async fn take_order(
+        conn: &mut AsyncPgConnection,
+        actor: i32,
+        id: i32,
+        receive: bool,
+    ) -> ApiResult<()> {
+        if receive {
+            let date = Utc::now().naive_utc();
+            PendingOrder::mark_as_received(conn, actor, id, date, 5.0, 0.0).await?;
+        } else {
+            ReceivedOrder::delete(conn, actor, id).await?;
+        }
+        Ok(())
+    }
+
+    /// Takes order `id` twice at once: the second try starts while the first
+    /// transaction still holds the order.
+    async fn take_order_twice(
+        pool: &Pool,
+        actor: i32,
+        id: i32,
+        receive: bool,
+    ) -> (ApiResult<()>, ApiResult<()>) {
+        let (taken, is_taken) = oneshot::channel();
+        let (finish, can_finish) = oneshot::channel();
+        let first = services::transaction(pool, |conn| {
+            async move {
+                take_order(conn, actor, id, receive).await?;
+                taken.send(()).unwrap();
+                can_finish.await.unwrap();
+                Ok(())
+            }
+            .scope_boxed()
+        });
+        let second = async {
+            is_taken.await.unwrap();
+            let second = services::transaction(pool, |conn| {
+                async move { take_order(conn, actor, id, receive).await }.scope_boxed()
+            });
+            let release = async {
+                sleep(Duration::from_millis(200)).await;
+                finish.send(()).unwrap();
+            };
+            join(second, release).await.0
+        };
+        join(first, second).await
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn orders_taken_twice_at_once_move_stock_once() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let actor = test_actor(conn.as_mut()).await;
+        let product = ProductBuilder::new(
+            &generate_token(),
+            "Raced",
+            false,
+            BigDecimal::from(1),
+            BigDecimal::from(2),
+        )
+        .build(conn.as_mut(), actor.id)
+        .await
+        .unwrap();
+        let pending = PendingOrderBuilder::new(product, 5.0)
+            .build(conn.as_mut(), actor.id)
+            .await
+            .unwrap();
+        drop(conn);
+        let amount = || async {
+            let mut conn = pool.get().await.unwrap();
+            Product::get(conn.as_mut(), product).await.unwrap().amount
+        };
+
+        let (first, second) = take_order_twice(&pool, actor.id, pending, true).await;
+        first.unwrap();
+        assert!(matches!(second, Err(ApiError::NotFound(_))));
+        assert_eq!(amount().await, 5.0);
+
+        let mut conn = pool.get().await.unwrap();
+        let received: ReceivedOrder = received_orders::table
+            .filter(received_orders::product_id.eq(product))
+            .first(conn.as_mut())
+            .await
+            .unwrap();
+        drop(conn);
+        let (first, second) = take_order_twice(&pool, actor.id, received.id, false).await;
+        first.unwrap();
+        assert!(matches!(second, Err(ApiError::NotFound(_))));
+        assert_eq!(amount().await, 0.0);
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn putting_a_fetched_user_keeps_the_password_hash() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let conn = conn.as_mut();
+        let user = test_actor(conn).await;
+        let changes = UserChanges {
+            name: None,
+            email: None,
+            password: Some(bcrypt::hash("secret", 4).unwrap()),
+        };
+        let user = User::patch(conn, user.id, user.id, None, changes)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let fetched = serde_json::to_value(&user).unwrap();
+        let update: UserUpdate = serde_json::from_value(fetched).unwrap();
+        update.update(conn, user.id).await.unwrap().unwrap();
+
+        let stored = User::from_id(conn, user.id).await.unwrap();
+        assert_eq!(stored.password, user.password);
+        assert!(verify("secret", &stored.password).unwrap());
+    }
+
+    #[test]
+    fn contains_patterns_escape_wildcards() {
+        assert_eq!(contains_pattern("oat"), "%oat%");
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+    }
+
+    #[test]
+    fn includes_parse_from_a_comma_separated_list() {
+        let parse = |value| ProductIncludes::from_value(ValueField::from_value(value));
+        assert_eq!(
+            parse("brand, suppliers,").unwrap(),
+            ProductIncludes {
+                brand: true,
+                categories: false,
+                suppliers: true,
+            }
+        );
+        assert_eq!(parse("").unwrap(), Default::default());
+        assert!(parse("brand,orders").is_err());
+    }
 }
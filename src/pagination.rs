@@ -0,0 +1,79 @@
+use diesel::result::Error as DieselError;
+use diesel::QueryResult;
+use rocket::form::{self, FromFormField, ValueField};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 500;
+
+/// The number of rows to return for a requested `limit`.
+pub fn page_size(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+/// Where the previous page ended: the sort key and id of its last row. Clients
+/// get it as an opaque string and pass it back with the same filters and sort.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    pub key: serde_json::Value,
+    pub id: i32,
+}
+
+impl Cursor {
+    /// The sort key as the sorted column's type.
+    pub fn key<T: DeserializeOwned>(&self) -> QueryResult<T> {
+        serde_json::from_value(self.key.clone())
+            .map_err(|error| DieselError::SerializationError(Box::new(error)))
+    }
+
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("a cursor always serializes");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let json = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for Cursor {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        Cursor::decode(field.value).ok_or_else(|| form::Error::validation("invalid cursor").into())
+    }
+}
+
+/// One page of a list. `next` is the cursor for the page after it, if there is
+/// one, and `total` counts every row matching the filters when asked for.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+impl<T: Serialize> Page<T> {
+    /// Builds a page of `limit` rows out of up to `limit + 1` rows sorted on
+    /// `sort`. The extra row only tells us there is another page.
+    pub fn new<C: Serialize>(mut items: Vec<T>, limit: i64, sort: C) -> Self {
+        let mut next = None;
+        if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            let last = serde_json::to_value(items.last()).expect("rows always serialize");
+            let column = serde_json::to_value(sort).expect("columns always serialize");
+            let cursor = Cursor {
+                key: last[column.as_str().unwrap_or_default()].clone(),
+                id: last["id"].as_i64().unwrap_or_default() as i32,
+            };
+            next = Some(cursor.encode());
+        }
+        Page {
+            items,
+            next,
+            total: None,
+        }
+    }
+}
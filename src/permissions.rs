@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+// Capabilities that roles grant. A new capability only needs a constant here,
+// an entry in `ALL` and a guard in `auth`; no migration is required.
+pub const ADMIN: &str = "admin";
+pub const VIEW_PENDING: &str = "view_pending";
+pub const VIEW_RECEIVED: &str = "view_received";
+pub const EDIT_PENDING: &str = "edit_pending";
+pub const CREATE_ORDERS: &str = "create_orders";
+pub const EDIT_RECEIVED: &str = "edit_received";
+pub const REMOVE_ORDERS: &str = "remove_orders";
+pub const EDIT_PRODUCTS: &str = "edit_products";
+pub const VIEW_PRODUCTS: &str = "view_products";
+pub const VIEW_SUPPLIERS: &str = "view_suppliers";
+
+pub const ALL: [&str; 10] = [
+    ADMIN,
+    VIEW_PENDING,
+    VIEW_RECEIVED,
+    EDIT_PENDING,
+    CREATE_ORDERS,
+    EDIT_RECEIVED,
+    REMOVE_ORDERS,
+    EDIT_PRODUCTS,
+    VIEW_PRODUCTS,
+    VIEW_SUPPLIERS,
+];
+
+pub fn is_known(permission: &str) -> bool {
+    ALL.contains(&permission)
+}
+
+/// The permissions a user holds through all of their roles.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PermissionSet(BTreeSet<String>);
+
+impl PermissionSet {
+    pub fn has(&self, permission: &str) -> bool {
+        self.0.contains(permission)
+    }
+
+    /// Keeps only the permissions named in `scopes`, so a scoped credential can
+    /// never do more than the user it belongs to.
+    pub fn restrict_to(self, scopes: &[String]) -> Self {
+        PermissionSet(
+            self.0
+                .into_iter()
+                .filter(|permission| scopes.contains(permission))
+                .collect(),
+        )
+    }
+}
+
+impl FromIterator<String> for PermissionSet {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        PermissionSet(iter.into_iter().collect())
+    }
+}
@@ -0,0 +1,28 @@
+use crate::auth::Admin;
+use crate::error::ApiResult;
+use crate::models::{AuditEntry, AuditFilter};
+use crate::pagination::{self, Cursor, Page};
+use crate::ServerState;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+
+pub fn routes() -> Vec<Route> {
+    routes![audit_log]
+}
+
+/// Lists audit entries, newest first. Any of `user_id`, `entity`, `entity_id`,
+/// `action`, `since` and `until` narrow the results.
+#[get("/audit_log?<limit>&<after>&<filter..>")]
+async fn audit_log(
+    _auth: Admin,
+    state: &State<ServerState>,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+    filter: AuditFilter,
+) -> ApiResult<Json<Page<AuditEntry>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    let entries = AuditEntry::search(conn.as_mut(), &filter, after.as_ref(), limit + 1).await?;
+    Ok(Json(Page::new(entries, limit, "id")))
+}
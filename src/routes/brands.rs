@@ -0,0 +1,179 @@
+use crate::auth::{EditProducts, ViewProducts};
+use crate::error::ApiResult;
+use crate::etag::{self, IfMatch, Tagged};
+use crate::idempotency::{self, IdempotencyKey};
+use crate::models::{Brand, BrandBuilder, BrandChanges, Similar};
+use crate::pagination::{self, Cursor, Page};
+use crate::services;
+use crate::validation;
+use crate::ServerState;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::RunQueryDsl;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        brands,
+        brand_names,
+        similar_brands,
+        brand,
+        new_brand,
+        update_brand,
+        patch_brand,
+        remove_brand
+    ]
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+pub struct NewBrand {
+    #[validate(custom = "validation::not_blank")]
+    pub name: String,
+}
+
+#[get("/brands?<limit>&<after>&<total>")]
+async fn brands(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+    total: bool,
+) -> ApiResult<Json<Page<Brand>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+    use crate::schema::brands::dsl::*;
+
+    let mut query = brands.into_boxed();
+    if let Some(after) = after {
+        query = query.filter(id.gt(after.id));
+    }
+    let rows = query.order(id).limit(limit + 1).load(conn.as_mut()).await?;
+    let mut page = Page::new(rows, limit, "id");
+    if total {
+        page.total = Some(brands.count().get_result(conn.as_mut()).await?);
+    }
+    Ok(Json(page))
+}
+
+#[get("/brands/names")]
+async fn brand_names(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+) -> ApiResult<Json<Vec<(String, i32)>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(Brand::get_names(conn.as_mut()).await?))
+}
+
+/// Brands whose names look like `q`, for when the exact spelling isn't known.
+#[get("/brands/similar?<q>&<limit>")]
+async fn similar_brands(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    q: &str,
+    limit: Option<i64>,
+) -> ApiResult<Json<Vec<Similar<Brand>>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(Brand::search_similar(conn.as_mut(), q, limit).await?))
+}
+
+#[get("/brands/<id>")]
+async fn brand(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<Tagged<Json<Brand>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let brand = Brand::get(conn.as_mut(), id).await?;
+    let updated_at = brand.updated_at;
+
+    Ok(Tagged(Json(brand), updated_at))
+}
+
+#[post("/brands", data = "<brand>")]
+async fn new_brand(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    idempotency_key: IdempotencyKey,
+    brand: Json<NewBrand>,
+) -> ApiResult<Json<i32>> {
+    brand.validate()?;
+    let idempotency_key = idempotency_key.with_body(&*brand)?;
+    idempotency::transaction(&state.db_pool, &idempotency_key, auth.user_id(), |conn| {
+        async move {
+            let builder = BrandBuilder::new(brand.into_inner().name);
+
+            Ok(builder.build(conn, auth.user_id()).await?)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(Json)
+}
+
+#[put("/brands/<id>", data = "<brand>")]
+async fn update_brand(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    brand: Json<Brand>,
+) -> ApiResult<Tagged<()>> {
+    brand.validate()?;
+    let mut brand = brand.into_inner();
+    brand.id = id;
+    if let Some(version) = if_match.version()? {
+        brand.updated_at = version;
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let brand = brand
+                .update(conn, auth.user_id())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), brand.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[patch("/brands/<id>", data = "<changes>")]
+async fn patch_brand(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    changes: Json<BrandChanges>,
+) -> ApiResult<Tagged<()>> {
+    changes.validate()?;
+    let version = if_match.version()?;
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let brand = Brand::patch(conn, auth.user_id(), id, version, changes.into_inner())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), brand.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/brands/<id>")]
+async fn remove_brand(auth: EditProducts, state: &State<ServerState>, id: i32) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            Brand::delete(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
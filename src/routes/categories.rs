@@ -0,0 +1,158 @@
+use crate::auth::{EditProducts, ViewProducts};
+use crate::error::ApiResult;
+use crate::etag::{self, IfMatch, Tagged};
+use crate::idempotency::{self, IdempotencyKey};
+use crate::models::{Category, CategoryBuilder, CategoryChanges, CategoryFilter};
+use crate::pagination::{self, Cursor, Page};
+use crate::services;
+use crate::validation;
+use crate::ServerState;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        categories,
+        category_names,
+        category,
+        new_category,
+        update_category,
+        patch_category,
+        remove_category
+    ]
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+pub struct NewCategory {
+    #[validate(custom = "validation::not_blank")]
+    pub name: String,
+}
+
+#[get("/categories?<limit>&<after>&<total>&<filter..>")]
+async fn categories(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+    total: bool,
+    filter: CategoryFilter,
+) -> ApiResult<Json<Page<Category>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    let rows = Category::search(conn.as_mut(), &filter, after.as_ref(), limit + 1).await?;
+    let mut page = Page::new(rows, limit, filter.sort.unwrap_or_default());
+    if total {
+        page.total = Some(Category::count(conn.as_mut(), &filter).await?);
+    }
+    Ok(Json(page))
+}
+
+#[get("/categories/names")]
+async fn category_names(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+) -> ApiResult<Json<Vec<(String, i32)>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(Category::get_names(conn.as_mut()).await?))
+}
+
+#[get("/categories/<id>")]
+async fn category(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<Tagged<Json<Category>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let category = Category::get(conn.as_mut(), id).await?;
+    let updated_at = category.updated_at;
+
+    Ok(Tagged(Json(category), updated_at))
+}
+
+#[post("/categories", data = "<category>")]
+async fn new_category(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    idempotency_key: IdempotencyKey,
+    category: Json<NewCategory>,
+) -> ApiResult<Json<i32>> {
+    category.validate()?;
+    let idempotency_key = idempotency_key.with_body(&*category)?;
+    idempotency::transaction(&state.db_pool, &idempotency_key, auth.user_id(), |conn| {
+        async move {
+            let builder = CategoryBuilder::new(category.into_inner().name);
+
+            Ok(builder.build(conn, auth.user_id()).await?)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(Json)
+}
+
+#[put("/categories/<id>", data = "<category>")]
+async fn update_category(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    category: Json<Category>,
+) -> ApiResult<Tagged<()>> {
+    category.validate()?;
+    let mut category = category.into_inner();
+    category.id = id;
+    if let Some(version) = if_match.version()? {
+        category.updated_at = version;
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let category = category
+                .update(conn, auth.user_id())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), category.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[patch("/categories/<id>", data = "<changes>")]
+async fn patch_category(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    changes: Json<CategoryChanges>,
+) -> ApiResult<Tagged<()>> {
+    changes.validate()?;
+    let version = if_match.version()?;
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let category = Category::patch(conn, auth.user_id(), id, version, changes.into_inner())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), category.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/categories/<id>")]
+async fn remove_category(auth: EditProducts, state: &State<ServerState>, id: i32) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            Category::delete(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
@@ -0,0 +1,66 @@
+use crate::auth::{AuthGuard, Credential};
+use crate::error::{ApiError, ApiResult};
+use crate::events::{can_subscribe, permission, Feed, LastEventId, AUTH_INTERVAL, POLL_INTERVAL};
+use crate::ServerState;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::time::{sleep, Instant};
+use rocket::{Route, State};
+
+pub fn routes() -> Vec<Route> {
+    routes![events]
+}
+
+/// Streams changes to products, brands, categories, suppliers and orders as
+/// server-sent events, leaving out those the caller isn't allowed to view.
+/// Each event's id can be sent back as `Last-Event-ID`, or as `last_event_id`
+/// where headers can't be set, to resume after it. The stream ends once the
+/// caller's credential expires or is revoked.
+#[get("/events?<last_event_id>")]
+async fn events(
+    mut auth: AuthGuard,
+    credential: Credential,
+    state: &State<ServerState>,
+    header: LastEventId,
+    last_event_id: Option<i32>,
+) -> ApiResult<EventStream![Event + '_]> {
+    if !can_subscribe(&auth.permissions) {
+        return Err(ApiError::Forbidden);
+    }
+    let mut feed = {
+        let mut conn = state.db_pool.get().await?;
+        Feed::start(conn.as_mut(), header.0.or(last_event_id)).await?
+    };
+
+    Ok(EventStream! {
+        let mut authenticated = Instant::now();
+        loop {
+            if authenticated.elapsed() >= AUTH_INTERVAL {
+                auth = match credential.authenticate(state).await {
+                    Ok(Some(auth)) if can_subscribe(&auth.permissions) => auth,
+                    _ => break,
+                };
+                authenticated = Instant::now();
+            }
+            // A failed check ends the stream, and the client resumes from its
+            // last event when it reconnects
+            let changes = match state.db_pool.get().await {
+                Ok(mut conn) => feed.next(conn.as_mut()).await,
+                Err(_) => break,
+            };
+            let changes = match changes {
+                Ok(changes) => changes,
+                Err(_) => break,
+            };
+            for change in changes {
+                let visible = permission(&change.entity)
+                    .is_some_and(|needed| auth.permissions.has(needed));
+                if visible {
+                    yield Event::json(&change)
+                        .id(change.id.to_string())
+                        .event(change.kind());
+                }
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    })
+}
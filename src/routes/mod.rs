@@ -0,0 +1,28 @@
+pub mod audit;
+pub mod brands;
+pub mod categories;
+pub mod events;
+pub mod orders;
+pub mod products;
+pub mod roles;
+pub mod suppliers;
+pub mod users;
+pub mod webhooks;
+
+use rocket::Route;
+
+pub fn routes() -> Vec<Route> {
+    [
+        products::routes(),
+        brands::routes(),
+        categories::routes(),
+        suppliers::routes(),
+        orders::routes(),
+        users::routes(),
+        roles::routes(),
+        audit::routes(),
+        events::routes(),
+        webhooks::routes(),
+    ]
+    .concat()
+}
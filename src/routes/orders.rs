@@ -0,0 +1,327 @@
+use crate::auth::{CreateOrders, EditPending, EditReceived, RemoveOrders, ViewPending, ViewReceived};
+use crate::error::ApiResult;
+use crate::etag::{self, IfMatch, Tagged};
+use crate::idempotency::{self, IdempotencyKey};
+use crate::models::{
+    PendingOrder, PendingOrderBuilder, PendingOrderChanges, PendingOrderFilter, ReceivedOrder,
+    ReceivedOrderChanges, ReceivedOrderFilter,
+};
+use crate::pagination::{self, Cursor, Page};
+use crate::services;
+use crate::validation;
+use crate::ServerState;
+use chrono::NaiveDateTime;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        pending_orders,
+        pending_order,
+        new_pending_order,
+        update_pending_order,
+        patch_pending_order,
+        remove_pending_order,
+        receive_order,
+        received_orders,
+        received_order,
+        update_received_order,
+        patch_received_order,
+        remove_received_order,
+        unreceive_order,
+    ]
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+pub struct NewPendingOrder {
+    pub product_id: i32,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub amount: f64,
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+#[validate(schema(function = "validate_receipt"))]
+pub struct Receipt {
+    pub date: NaiveDateTime,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub actually_received: f64,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub damaged: f64,
+}
+
+fn validate_receipt(receipt: &Receipt) -> Result<(), ValidationError> {
+    validation::received_amounts(receipt.actually_received, receipt.damaged)
+}
+
+#[get("/pending_orders?<limit>&<after>&<total>&<filter..>")]
+async fn pending_orders(
+    _auth: ViewPending,
+    state: &State<ServerState>,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+    total: bool,
+    filter: PendingOrderFilter,
+) -> ApiResult<Json<Page<PendingOrder>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    let rows = PendingOrder::search(conn.as_mut(), &filter, after.as_ref(), limit + 1).await?;
+    let mut page = Page::new(rows, limit, filter.sort.unwrap_or_default());
+    if total {
+        page.total = Some(PendingOrder::count(conn.as_mut(), &filter).await?);
+    }
+    Ok(Json(page))
+}
+
+#[get("/pending_orders/<id>")]
+async fn pending_order(
+    _auth: ViewPending,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<Tagged<Json<PendingOrder>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let order = PendingOrder::get(conn.as_mut(), id).await?;
+    let updated_at = order.updated_at;
+
+    Ok(Tagged(Json(order), updated_at))
+}
+
+#[post("/pending_orders", data = "<order>")]
+async fn new_pending_order(
+    auth: CreateOrders,
+    state: &State<ServerState>,
+    idempotency_key: IdempotencyKey,
+    order: Json<NewPendingOrder>,
+) -> ApiResult<Json<i32>> {
+    order.validate()?;
+    let idempotency_key = idempotency_key.with_body(&*order)?;
+    idempotency::transaction(&state.db_pool, &idempotency_key, auth.user_id(), |conn| {
+        async move {
+            Ok(PendingOrderBuilder::new(order.product_id, order.amount)
+                .build(conn, auth.user_id())
+                .await?)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(Json)
+}
+
+#[put("/pending_orders/<id>", data = "<order>")]
+async fn update_pending_order(
+    auth: EditPending,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    order: Json<PendingOrder>,
+) -> ApiResult<Tagged<()>> {
+    order.validate()?;
+    let mut order = order.into_inner();
+    order.id = id;
+    if let Some(version) = if_match.version()? {
+        order.updated_at = version;
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let order = order
+                .update(conn, auth.user_id())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), order.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[patch("/pending_orders/<id>", data = "<changes>")]
+async fn patch_pending_order(
+    auth: EditPending,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    changes: Json<PendingOrderChanges>,
+) -> ApiResult<Tagged<()>> {
+    changes.validate()?;
+    let version = if_match.version()?;
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let order =
+                PendingOrder::patch(conn, auth.user_id(), id, version, changes.into_inner())
+                    .await?
+                    .ok_or_else(etag::stale)?;
+            Ok(Tagged((), order.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/pending_orders/<id>")]
+async fn remove_pending_order(
+    auth: RemoveOrders,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            PendingOrder::delete(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Moves a pending order to the received orders, returning the id of the new
+/// received order.
+#[post("/pending_orders/<id>/receive", data = "<receipt>")]
+async fn receive_order(
+    auth: EditReceived,
+    state: &State<ServerState>,
+    idempotency_key: IdempotencyKey,
+    id: i32,
+    receipt: Json<Receipt>,
+) -> ApiResult<Json<i32>> {
+    receipt.validate()?;
+    let idempotency_key = idempotency_key.with_body(&*receipt)?;
+    idempotency::transaction(&state.db_pool, &idempotency_key, auth.user_id(), |conn| {
+        async move {
+            Ok(PendingOrder::mark_as_received(
+                conn,
+                auth.user_id(),
+                id,
+                receipt.date,
+                receipt.actually_received,
+                receipt.damaged,
+            )
+            .await?
+            .id)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(Json)
+}
+
+#[get("/received_orders?<limit>&<after>&<total>&<filter..>")]
+async fn received_orders(
+    _auth: ViewReceived,
+    state: &State<ServerState>,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+    total: bool,
+    filter: ReceivedOrderFilter,
+) -> ApiResult<Json<Page<ReceivedOrder>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    let rows = ReceivedOrder::search(conn.as_mut(), &filter, after.as_ref(), limit + 1).await?;
+    let mut page = Page::new(rows, limit, filter.sort.unwrap_or_default());
+    if total {
+        page.total = Some(ReceivedOrder::count(conn.as_mut(), &filter).await?);
+    }
+    Ok(Json(page))
+}
+
+#[get("/received_orders/<id>")]
+async fn received_order(
+    _auth: ViewReceived,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<Tagged<Json<ReceivedOrder>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let order = ReceivedOrder::get(conn.as_mut(), id).await?;
+    let updated_at = order.updated_at;
+
+    Ok(Tagged(Json(order), updated_at))
+}
+
+#[put("/received_orders/<id>", data = "<order>")]
+async fn update_received_order(
+    auth: EditReceived,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    order: Json<ReceivedOrder>,
+) -> ApiResult<Tagged<()>> {
+    order.validate()?;
+    let mut order = order.into_inner();
+    order.id = id;
+    if let Some(version) = if_match.version()? {
+        order.updated_at = version;
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let order = order
+                .update(conn, auth.user_id())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), order.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[patch("/received_orders/<id>", data = "<changes>")]
+async fn patch_received_order(
+    auth: EditReceived,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    changes: Json<ReceivedOrderChanges>,
+) -> ApiResult<Tagged<()>> {
+    changes.validate()?;
+    let version = if_match.version()?;
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let order =
+                ReceivedOrder::patch(conn, auth.user_id(), id, version, changes.into_inner())
+                    .await?
+                    .ok_or_else(etag::stale)?;
+            Ok(Tagged((), order.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/received_orders/<id>")]
+async fn remove_received_order(
+    auth: RemoveOrders,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            ReceivedOrder::delete(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Puts a received order back into the pending orders.
+#[post("/received_orders/<id>/unreceive")]
+async fn unreceive_order(
+    auth: EditReceived,
+    state: &State<ServerState>,
+    idempotency_key: IdempotencyKey,
+    id: i32,
+) -> ApiResult<()> {
+    idempotency::transaction(&state.db_pool, &idempotency_key, auth.user_id(), |conn| {
+        async move {
+            ReceivedOrder::mark_as_pending(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
@@ -0,0 +1,486 @@
+use crate::auth::{EditProducts, ViewProducts};
+use crate::error::{ApiError, ApiResult};
+use crate::etag::{self, IfMatch, Tagged};
+use crate::idempotency::{self, IdempotencyKey};
+use crate::models::{
+    Brand, Category, Product, ProductBuilder, ProductChanges, ProductFilter, ProductIncludes,
+    ProductMatch, ProductWithRelations, Similar, StockMovement, StockMovementReason, Supplier,
+};
+use crate::pagination::{self, Cursor, Page};
+use crate::services;
+use crate::validation;
+use crate::ServerState;
+use bigdecimal::BigDecimal;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        products,
+        product_names,
+        similar_products,
+        search_products,
+        product,
+        new_product,
+        update_product,
+        patch_product,
+        remove_product,
+        product_categories,
+        add_product_category,
+        remove_product_category,
+        product_suppliers,
+        add_product_supplier,
+        remove_product_supplier,
+        product_brand,
+        add_product_brand,
+        remove_product_brand,
+        stock_movements,
+        new_stock_movement,
+    ]
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+pub struct NewProduct {
+    #[validate(custom = "validation::upc")]
+    pub upc: String,
+    #[validate(custom = "validation::not_blank")]
+    pub name: String,
+    pub description: String,
+    pub measure_by_weight: bool,
+    #[validate(custom = "validation::price")]
+    pub cost_price_per_unit: BigDecimal,
+    #[validate(custom = "validation::price")]
+    pub selling_price_per_unit: BigDecimal,
+    pub categories: Option<Vec<i32>>,
+    pub suppliers: Option<Vec<i32>>,
+    pub brand: Option<i32>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub buy_level: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+#[validate(schema(function = "validate_stock_movement"))]
+pub struct NewStockMovement {
+    pub quantity: f64,
+    pub reason: StockMovementReason,
+}
+
+fn validate_stock_movement(movement: &NewStockMovement) -> Result<(), ValidationError> {
+    validation::movement_quantity(movement.reason, movement.quantity)
+}
+
+#[get("/products?<limit>&<after>&<total>&<include>&<filter..>")]
+async fn products(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+    total: bool,
+    include: Option<ProductIncludes>,
+    filter: ProductFilter,
+) -> ApiResult<Json<Page<ProductWithRelations>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    let rows = Product::search(conn.as_mut(), &filter, after.as_ref(), limit + 1).await?;
+    let rows = Product::with_relations(conn.as_mut(), rows, include.unwrap_or_default()).await?;
+    let mut page = Page::new(rows, limit, filter.sort.unwrap_or_default());
+    if total {
+        page.total = Some(Product::count(conn.as_mut(), &filter).await?);
+    }
+    Ok(Json(page))
+}
+
+#[get("/products/names")]
+async fn product_names(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+) -> ApiResult<Json<Vec<(String, String, i32)>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(Product::get_names(conn.as_mut()).await?))
+}
+
+/// Products whose names look like `q`, for when the exact spelling isn't known.
+#[get("/products/similar?<q>&<limit>")]
+async fn similar_products(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    q: &str,
+    limit: Option<i64>,
+) -> ApiResult<Json<Vec<Similar<Product>>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(
+        Product::search_similar(conn.as_mut(), q, limit).await?,
+    ))
+}
+
+/// Full-text search over UPCs, names and descriptions. When no words match,
+/// falls back to names that are spelled similarly.
+#[get("/products/search?<q>&<limit>")]
+async fn search_products(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    q: &str,
+    limit: Option<i64>,
+) -> ApiResult<Json<Vec<ProductMatch>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    let matches = Product::search_text(conn.as_mut(), q, limit).await?;
+    if !matches.is_empty() {
+        return Ok(Json(matches));
+    }
+    let similar = Product::search_similar(conn.as_mut(), q, limit).await?;
+    Ok(Json(similar.into_iter().map(ProductMatch::from).collect()))
+}
+
+#[get("/products/<id>?<include>")]
+async fn product(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    id: i32,
+    include: Option<ProductIncludes>,
+) -> ApiResult<Tagged<Json<ProductWithRelations>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let product = Product::get(conn.as_mut(), id).await?;
+    let updated_at = product.updated_at;
+    let mut products =
+        Product::with_relations(conn.as_mut(), vec![product], include.unwrap_or_default()).await?;
+
+    Ok(Tagged(Json(products.remove(0)), updated_at))
+}
+
+#[post("/products", data = "<product>")]
+async fn new_product(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    idempotency_key: IdempotencyKey,
+    product: Json<NewProduct>,
+) -> ApiResult<Json<i32>> {
+    product.validate()?;
+    let idempotency_key = idempotency_key.with_body(&*product)?;
+    let product = product.into_inner();
+
+    let mut builder = ProductBuilder::new(
+        &product.upc,
+        &product.name,
+        product.measure_by_weight,
+        product.cost_price_per_unit,
+        product.selling_price_per_unit,
+    );
+    if let Some(brand) = product.brand {
+        builder = builder.with_brand(brand);
+    }
+    if let Some(categories) = product.categories {
+        builder = builder.with_categories(&categories);
+    }
+
+    if let Some(suppliers) = product.suppliers {
+        builder = builder.with_suppliers(&suppliers);
+    }
+
+    if let Some(buy_level) = product.buy_level {
+        builder = builder.with_buy_level(buy_level)
+    }
+
+    idempotency::transaction(&state.db_pool, &idempotency_key, auth.user_id(), |conn| {
+        async move {
+            Ok(builder
+                .with_description(&product.description)
+                .build(conn, auth.user_id())
+                .await?)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(Json)
+}
+
+#[put("/products/<id>", data = "<product>")]
+async fn update_product(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    product: Json<Product>,
+) -> ApiResult<Tagged<()>> {
+    product.validate()?;
+    let mut product = product.into_inner();
+    product.id = id;
+    if let Some(version) = if_match.version()? {
+        product.updated_at = version;
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let product = product
+                .update(conn, auth.user_id())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), product.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[patch("/products/<id>", data = "<changes>")]
+async fn patch_product(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    changes: Json<ProductChanges>,
+) -> ApiResult<Tagged<()>> {
+    changes.validate()?;
+    let version = if_match.version()?;
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let product = Product::patch(conn, auth.user_id(), id, version, changes.into_inner())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), product.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/products/<id>")]
+async fn remove_product(auth: EditProducts, state: &State<ServerState>, id: i32) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            Product::get(conn, id)
+                .await?
+                .delete(conn, auth.user_id())
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[get("/products/<product_id>/categories")]
+async fn product_categories(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+) -> ApiResult<Json<Vec<Category>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(
+        Product::get(conn.as_mut(), product_id)
+            .await?
+            .get_categories(conn.as_mut())
+            .await?,
+    ))
+}
+
+#[put("/products/<product_id>/categories/<category_id>")]
+async fn add_product_category(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+    category_id: i32,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let product = Product::get(conn, product_id).await?;
+            product
+                .add_category(conn, auth.user_id(), category_id)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/products/<product_id>/categories/<category_id>")]
+async fn remove_product_category(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+    category_id: i32,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let product = Product::get(conn, product_id).await?;
+            product
+                .remove_category(conn, auth.user_id(), category_id)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[get("/products/<product_id>/suppliers")]
+async fn product_suppliers(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+) -> ApiResult<Json<Vec<Supplier>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(
+        Product::get(conn.as_mut(), product_id)
+            .await?
+            .get_suppliers(conn.as_mut())
+            .await?,
+    ))
+}
+
+#[put("/products/<product_id>/suppliers/<supplier_id>")]
+async fn add_product_supplier(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+    supplier_id: i32,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let product = Product::get(conn, product_id).await?;
+            product
+                .add_supplier(conn, auth.user_id(), supplier_id)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/products/<product_id>/suppliers/<supplier_id>")]
+async fn remove_product_supplier(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+    supplier_id: i32,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let product = Product::get(conn, product_id).await?;
+            product
+                .remove_supplier(conn, auth.user_id(), supplier_id)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[get("/products/<product_id>/brand")]
+async fn product_brand(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+) -> ApiResult<Json<Option<Brand>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(
+        Product::get(conn.as_mut(), product_id)
+            .await?
+            .get_brand(conn.as_mut())
+            .await?,
+    ))
+}
+
+#[put("/products/<product_id>/brand/<brand_id>")]
+async fn add_product_brand(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+    brand_id: i32,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let product = Product::get(conn, product_id).await?;
+            product.add_brand(conn, auth.user_id(), brand_id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/products/<product_id>/brand/<brand_id>")]
+async fn remove_product_brand(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+    brand_id: i32,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let product = Product::get(conn, product_id).await?;
+            product.remove_brand(conn, auth.user_id(), brand_id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// The product's stock history, oldest first.
+#[get("/products/<product_id>/stock_movements?<limit>&<after>")]
+async fn stock_movements(
+    _auth: ViewProducts,
+    state: &State<ServerState>,
+    product_id: i32,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+) -> ApiResult<Json<Page<StockMovement>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    let movements = Product::get(conn.as_mut(), product_id)
+        .await?
+        .get_stock_movements(conn.as_mut(), after.as_ref(), limit + 1)
+        .await?;
+    Ok(Json(Page::new(movements, limit, "id")))
+}
+
+/// Records a sale, adjustment, transfer or the like against the product's
+/// stock. Receipts come from receiving orders instead.
+#[post("/products/<product_id>/stock_movements", data = "<movement>")]
+async fn new_stock_movement(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    idempotency_key: IdempotencyKey,
+    product_id: i32,
+    movement: Json<NewStockMovement>,
+) -> ApiResult<Json<StockMovement>> {
+    movement.validate()?;
+    if movement.reason == StockMovementReason::Receipt {
+        return Err(ApiError::Validation(String::from(
+            "Receipts are recorded by receiving a pending order",
+        )));
+    }
+    let idempotency_key = idempotency_key.with_body(&*movement)?;
+    idempotency::transaction(&state.db_pool, &idempotency_key, auth.user_id(), |conn| {
+        async move {
+            Ok(StockMovement::record(
+                conn,
+                auth.user_id(),
+                product_id,
+                movement.quantity,
+                movement.reason,
+                None,
+            )
+            .await?)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(Json)
+}
@@ -0,0 +1,116 @@
+use crate::auth::Admin;
+use crate::error::{ApiError, ApiResult};
+use crate::models::{Role, RoleWithPermissions};
+use crate::permissions;
+use crate::services;
+use crate::ServerState;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::Deserialize;
+
+pub fn routes() -> Vec<Route> {
+    routes![roles, role, new_role, update_role, remove_role]
+}
+
+#[derive(Deserialize)]
+pub struct RoleRequest {
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+impl RoleRequest {
+    fn validate(&self) -> ApiResult<()> {
+        match self
+            .permissions
+            .iter()
+            .find(|permission| !permissions::is_known(permission))
+        {
+            Some(permission) => Err(ApiError::Validation(format!(
+                "Unknown permission {}",
+                permission
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[get("/roles")]
+async fn roles(
+    _auth: Admin,
+    state: &State<ServerState>,
+) -> ApiResult<Json<Vec<RoleWithPermissions>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let mut roles = Vec::new();
+    for role in Role::get_all(conn.as_mut()).await? {
+        roles.push(role.with_permissions(conn.as_mut()).await?);
+    }
+    Ok(Json(roles))
+}
+
+#[get("/roles/<id>")]
+async fn role(
+    _auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<Json<RoleWithPermissions>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let role = Role::get(conn.as_mut(), id).await?;
+    Ok(Json(role.with_permissions(conn.as_mut()).await?))
+}
+
+#[post("/roles", data = "<request>")]
+async fn new_role(
+    auth: Admin,
+    state: &State<ServerState>,
+    request: Json<RoleRequest>,
+) -> ApiResult<Json<RoleWithPermissions>> {
+    request.validate()?;
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            Ok(Json(
+                Role::create(conn, auth.user_id(), &request.name, &request.permissions).await?,
+            ))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Renames a role and replaces its permissions. Users holding the role pick up
+/// the change the next time their access token is refreshed.
+#[put("/roles/<id>", data = "<request>")]
+async fn update_role(
+    auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+    request: Json<RoleRequest>,
+) -> ApiResult<()> {
+    request.validate()?;
+    let request = request.into_inner();
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let mut role = Role::get(conn, id).await?;
+            role.name = request.name;
+            role.update(conn, auth.user_id(), &request.permissions)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/roles/<id>")]
+async fn remove_role(auth: Admin, state: &State<ServerState>, id: i32) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            Role::delete(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
@@ -0,0 +1,188 @@
+use crate::auth::{EditProducts, ViewSuppliers};
+use crate::error::ApiResult;
+use crate::etag::{self, IfMatch, Tagged};
+use crate::idempotency::{self, IdempotencyKey};
+use crate::models::{Similar, Supplier, SupplierBuilder, SupplierChanges, SupplierFilter};
+use crate::pagination::{self, Cursor, Page};
+use crate::services;
+use crate::validation;
+use crate::ServerState;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        suppliers,
+        supplier_names,
+        similar_suppliers,
+        supplier,
+        new_supplier,
+        update_supplier,
+        patch_supplier,
+        remove_supplier
+    ]
+}
+
+#[derive(Deserialize, Serialize, Validate)]
+pub struct NewSupplier {
+    #[validate(custom = "validation::not_blank")]
+    pub name: String,
+    #[validate(custom = "validation::phone_number")]
+    pub phone_number: Option<String>,
+    #[validate(email(message = "must be an email address"))]
+    pub email: Option<String>,
+}
+
+#[get("/suppliers?<limit>&<after>&<total>&<filter..>")]
+async fn suppliers(
+    _auth: ViewSuppliers,
+    state: &State<ServerState>,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+    total: bool,
+    filter: SupplierFilter,
+) -> ApiResult<Json<Page<Supplier>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    let rows = Supplier::search(conn.as_mut(), &filter, after.as_ref(), limit + 1).await?;
+    let mut page = Page::new(rows, limit, filter.sort.unwrap_or_default());
+    if total {
+        page.total = Some(Supplier::count(conn.as_mut(), &filter).await?);
+    }
+    Ok(Json(page))
+}
+
+#[get("/suppliers/names")]
+async fn supplier_names(
+    _auth: ViewSuppliers,
+    state: &State<ServerState>,
+) -> ApiResult<Json<Vec<(String, i32)>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(Supplier::get_names(conn.as_mut()).await?))
+}
+
+/// Suppliers whose names look like `q`, for when the exact spelling isn't known.
+#[get("/suppliers/similar?<q>&<limit>")]
+async fn similar_suppliers(
+    _auth: ViewSuppliers,
+    state: &State<ServerState>,
+    q: &str,
+    limit: Option<i64>,
+) -> ApiResult<Json<Vec<Similar<Supplier>>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(
+        Supplier::search_similar(conn.as_mut(), q, limit).await?,
+    ))
+}
+
+#[get("/suppliers/<id>")]
+async fn supplier(
+    _auth: ViewSuppliers,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<Tagged<Json<Supplier>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let supplier = Supplier::get(conn.as_mut(), id).await?;
+    let updated_at = supplier.updated_at;
+
+    Ok(Tagged(Json(supplier), updated_at))
+}
+
+#[post("/suppliers", data = "<supplier>")]
+async fn new_supplier(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    idempotency_key: IdempotencyKey,
+    supplier: Json<NewSupplier>,
+) -> ApiResult<Json<i32>> {
+    supplier.validate()?;
+    let idempotency_key = idempotency_key.with_body(&*supplier)?;
+    let supplier = supplier.into_inner();
+    idempotency::transaction(&state.db_pool, &idempotency_key, auth.user_id(), |conn| {
+        async move {
+            let mut builder = SupplierBuilder::new(supplier.name);
+
+            if let Some(phone_number) = supplier.phone_number {
+                builder = builder.with_phone_number(phone_number);
+            }
+
+            if let Some(email) = supplier.email {
+                builder = builder.with_email(email);
+            }
+
+            Ok(builder.build(conn, auth.user_id()).await?)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(Json)
+}
+
+#[put("/suppliers/<id>", data = "<supplier>")]
+async fn update_supplier(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    supplier: Json<Supplier>,
+) -> ApiResult<Tagged<()>> {
+    supplier.validate()?;
+    let mut supplier = supplier.into_inner();
+    supplier.id = id;
+    if let Some(version) = if_match.version()? {
+        supplier.updated_at = version;
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let supplier = supplier
+                .update(conn, auth.user_id())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), supplier.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[patch("/suppliers/<id>", data = "<changes>")]
+async fn patch_supplier(
+    auth: EditProducts,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    changes: Json<SupplierChanges>,
+) -> ApiResult<Tagged<()>> {
+    changes.validate()?;
+    let version = if_match.version()?;
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let supplier = Supplier::patch(conn, auth.user_id(), id, version, changes.into_inner())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), supplier.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/suppliers/<id>")]
+async fn remove_supplier(auth: EditProducts, state: &State<ServerState>, id: i32) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            Supplier::delete(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
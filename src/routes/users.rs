@@ -0,0 +1,245 @@
+use crate::auth::{Admin, AuthGuard, Credentials};
+use crate::error::{ApiError, ApiResult};
+use crate::etag::{self, IfMatch, Tagged};
+use crate::models::{
+    AuditEntry, NewUserRow, Preference, Role, User, UserBuilder, UserChanges, UserUpdate,
+};
+use crate::permissions::PermissionSet;
+use crate::services;
+use crate::ServerState;
+use bcrypt::hash;
+use diesel::QueryDsl;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::RunQueryDsl;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::Deserialize;
+use std::env;
+
+const DEFAULT_COST: usize = 10;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        user_by_name,
+        user,
+        signup,
+        update_user,
+        patch_user,
+        remove_user,
+        user_permissions,
+        user_roles,
+        set_user_roles,
+        permissions,
+        initialize,
+    ]
+}
+
+#[derive(Deserialize)]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub roles: Vec<i32>,
+}
+
+#[get("/users?<name>")]
+async fn user_by_name(
+    _auth: Admin,
+    state: &State<ServerState>,
+    name: String,
+) -> ApiResult<Json<User>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(User::from_name(conn.as_mut(), &name).await?))
+}
+
+#[get("/users/<id>")]
+async fn user(_auth: Admin, state: &State<ServerState>, id: i32) -> ApiResult<Tagged<Json<User>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let user = User::from_id(conn.as_mut(), id).await?;
+    let updated_at = user.updated_at;
+
+    Ok(Tagged(Json(user), updated_at))
+}
+
+#[post("/users", data = "<new_user>")]
+async fn signup(auth: Admin, state: &State<ServerState>, new_user: Json<NewUser>) -> ApiResult<()> {
+    let new_user = new_user.into_inner();
+    let builder =
+        UserBuilder::new(new_user.username, &new_user.password).with_roles(&new_user.roles);
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            builder.build(conn, auth.user_id()).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[put("/users/<id>", data = "<user>")]
+async fn update_user(
+    auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    user: Json<UserUpdate>,
+) -> ApiResult<Tagged<()>> {
+    let mut user = user.into_inner();
+    user.id = id;
+    if let Some(version) = if_match.version()? {
+        user.updated_at = version;
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let user = user
+                .update(conn, auth.user_id())
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), user.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[patch("/users/<id>", data = "<changes>")]
+async fn patch_user(
+    auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+    if_match: IfMatch,
+    changes: Json<UserChanges>,
+) -> ApiResult<Tagged<()>> {
+    let version = if_match.version()?;
+    let mut changes = changes.into_inner();
+    if let Some(password) = changes.password {
+        changes.password = Some(hash(
+            password + &env::var("PEPPER").expect("PEPPER must be set"),
+            DEFAULT_COST as u32,
+        )?);
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let user = User::patch(conn, auth.user_id(), id, version, changes)
+                .await?
+                .ok_or_else(etag::stale)?;
+            Ok(Tagged((), user.updated_at))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/users/<id>")]
+async fn remove_user(auth: Admin, state: &State<ServerState>, id: i32) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            User::delete(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[get("/users/<id>/permissions")]
+async fn user_permissions(
+    _auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<Json<PermissionSet>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let user = User::from_id(conn.as_mut(), id).await?;
+
+    Ok(Json(user.get_permissions(conn.as_mut()).await?))
+}
+
+#[get("/users/<id>/roles")]
+async fn user_roles(
+    _auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+) -> ApiResult<Json<Vec<Role>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    let user = User::from_id(conn.as_mut(), id).await?;
+
+    Ok(Json(user.get_roles(conn.as_mut()).await?))
+}
+
+/// Replaces the roles a user holds with the given role ids.
+#[put("/users/<id>/roles", data = "<role_ids>")]
+async fn set_user_roles(
+    auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+    role_ids: Json<Vec<i32>>,
+) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let user = User::from_id(conn, id).await?;
+            User::set_roles(conn, auth.user_id(), user.id, &role_ids).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[get("/permissions")]
+async fn permissions(auth: AuthGuard) -> Json<PermissionSet> {
+    Json(auth.permissions)
+}
+
+/// Creates the first admin account. Only works while there are no users.
+#[post("/initialize", data = "<credentials>")]
+async fn initialize(state: &State<ServerState>, credentials: Json<Credentials>) -> ApiResult<()> {
+    let credentials = credentials.into_inner();
+    let password_hash = hash(
+        credentials.password + &env::var("PEPPER").expect("PEPPER must be set"),
+        DEFAULT_COST as u32,
+    )?;
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            use crate::schema::preferences::dsl::*;
+            use crate::schema::users::dsl::*;
+
+            // Concurrent calls would otherwise both count no users and both
+            // create an admin
+            diesel::sql_query("LOCK TABLE users IN EXCLUSIVE MODE")
+                .execute(conn)
+                .await?;
+            let n_users: i64 = users.count().get_result(conn).await?;
+
+            if n_users != 0 {
+                return Err(ApiError::Conflict(String::from("Already initialized")));
+            }
+
+            let row: User = diesel::insert_into(users)
+                .values(NewUserRow {
+                    email: String::from(""),
+                    name: credentials.username,
+                    password: password_hash,
+                })
+                .get_result(conn)
+                .await?;
+            // There is nobody else yet, so the first admin is recorded as creating themselves
+            AuditEntry::record(conn, row.id, "user", row.id, None, Some(&row)).await?;
+
+            let admin_role = Role::from_name(conn, "Admin").await?;
+            User::set_roles(conn, row.id, row.id, &[admin_role.id]).await?;
+
+            diesel::insert_into(preferences)
+                .values(Preference { user_id: row.id })
+                .execute(conn)
+                .await?;
+
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
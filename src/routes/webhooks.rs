@@ -0,0 +1,118 @@
+use crate::auth::Admin;
+use crate::error::{ApiError, ApiResult};
+use crate::models::{Webhook, WebhookDelivery};
+use crate::pagination::{self, Cursor, Page};
+use crate::services;
+use crate::webhooks::{deliver, http_client, is_known, validate_url};
+use crate::ServerState;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        webhooks,
+        new_webhook,
+        remove_webhook,
+        webhook_deliveries,
+        redeliver,
+    ]
+}
+
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<String>,
+}
+
+/// A new webhook and the secret its deliveries are signed with, which is only
+/// shown this once.
+#[derive(Serialize)]
+pub struct CreatedWebhook {
+    pub secret: String,
+    pub webhook: Webhook,
+}
+
+#[get("/webhooks")]
+async fn webhooks(_auth: Admin, state: &State<ServerState>) -> ApiResult<Json<Vec<Webhook>>> {
+    let mut conn = state.db_pool.get().await?;
+
+    Ok(Json(Webhook::get_all(conn.as_mut()).await?))
+}
+
+#[post("/webhooks", data = "<webhook>")]
+async fn new_webhook(
+    auth: Admin,
+    state: &State<ServerState>,
+    webhook: Json<NewWebhook>,
+) -> ApiResult<Json<CreatedWebhook>> {
+    let webhook = webhook.into_inner();
+    validate_url(&webhook.url)?;
+    if webhook.events.is_empty() {
+        return Err(ApiError::Validation(String::from("No events to send")));
+    }
+    if let Some(event) = webhook.events.iter().find(|event| !is_known(event)) {
+        return Err(ApiError::Validation(format!("Unknown event {}", event)));
+    }
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            let mut webhook =
+                Webhook::create(conn, auth.user_id(), webhook.url, webhook.events).await?;
+            let secret = std::mem::take(&mut webhook.secret);
+            Ok(Json(CreatedWebhook { secret, webhook }))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[delete("/webhooks/<id>")]
+async fn remove_webhook(auth: Admin, state: &State<ServerState>, id: i32) -> ApiResult<()> {
+    services::transaction(&state.db_pool, |conn| {
+        async move {
+            Webhook::delete(conn, auth.user_id(), id).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// The webhook's delivery log, newest first.
+#[get("/webhooks/<id>/deliveries?<limit>&<after>")]
+async fn webhook_deliveries(
+    _auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+    limit: Option<i64>,
+    after: Option<Cursor>,
+) -> ApiResult<Json<Page<WebhookDelivery>>> {
+    let limit = pagination::page_size(limit);
+    let mut conn = state.db_pool.get().await?;
+
+    Webhook::get(conn.as_mut(), id).await?;
+    let deliveries =
+        WebhookDelivery::for_webhook(conn.as_mut(), id, after.as_ref(), limit + 1).await?;
+    Ok(Json(Page::new(deliveries, limit, "id")))
+}
+
+/// Sends a delivery again right away, whether or not it already succeeded,
+/// and returns how it went.
+#[post("/webhooks/<id>/deliveries/<delivery_id>/redeliver")]
+async fn redeliver(
+    _auth: Admin,
+    state: &State<ServerState>,
+    id: i32,
+    delivery_id: i32,
+) -> ApiResult<Json<WebhookDelivery>> {
+    let delivery = {
+        let mut conn = state.db_pool.get().await?;
+        WebhookDelivery::get(conn.as_mut(), delivery_id).await?
+    };
+    if delivery.webhook_id != id {
+        return Err(ApiError::NotFound(String::from("No such delivery")));
+    }
+    let client = http_client();
+    Ok(Json(deliver(&state.db_pool, &client, &delivery).await?))
+}
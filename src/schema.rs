@@ -1,18 +1,67 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "audit_action"))]
+    pub struct AuditAction;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "stock_movement_reason"))]
+    pub struct StockMovementReason;
+}
+
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        name -> Text,
+        key_hash -> Text,
+        prefix -> Text,
+        created_by -> Int4,
+        scopes -> Array<Nullable<Text>>,
+        created -> Timestamp,
+        revoked -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::AuditAction;
+
+    audit_log (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        occurred_at -> Timestamp,
+        entity -> Text,
+        entity_id -> Int4,
+        action -> AuditAction,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+    }
+}
+
 diesel::table! {
     brands (id) {
         id -> Int4,
         name -> Text,
-        products -> Array<Nullable<Int4>>,
+        updated_at -> Timestamp,
     }
 }
 
 diesel::table! {
     categories (id) {
         id -> Int4,
-        products -> Array<Nullable<Int4>>,
         name -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    idempotency_keys (user_id, key) {
+        user_id -> Int4,
+        key -> Text,
+        request -> Text,
+        response -> Nullable<Jsonb>,
+        created -> Timestamp,
     }
 }
 
@@ -21,28 +70,27 @@ diesel::table! {
         id -> Int4,
         product_id -> Int4,
         amount -> Float8,
+        updated_at -> Timestamp,
     }
 }
 
 diesel::table! {
-    permissions (user_id) {
+    preferences (user_id) {
         user_id -> Int4,
-        admin -> Bool,
-        view_pending -> Bool,
-        view_received -> Bool,
-        edit_pending -> Bool,
-        create_orders -> Bool,
-        edit_received -> Bool,
-        view_products -> Bool,
-        edit_products -> Bool,
-        remove_orders -> Bool,
-        view_suppliers -> Bool,
     }
 }
 
 diesel::table! {
-    preferences (user_id) {
-        user_id -> Int4,
+    product_categories (product_id, category_id) {
+        product_id -> Int4,
+        category_id -> Int4,
+    }
+}
+
+diesel::table! {
+    product_suppliers (product_id, supplier_id) {
+        product_id -> Int4,
+        supplier_id -> Int4,
     }
 }
 
@@ -60,6 +108,8 @@ diesel::table! {
         sale_end -> Nullable<Timestamp>,
         buy_level -> Nullable<Float8>,
         sale_price -> Nullable<Numeric>,
+        brand_id -> Nullable<Int4>,
+        updated_at -> Timestamp,
     }
 }
 
@@ -71,16 +121,62 @@ diesel::table! {
         gross_amount -> Float8,
         actually_received -> Float8,
         damaged -> Float8,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    role_permissions (role_id, permission) {
+        role_id -> Int4,
+        permission -> Text,
+    }
+}
+
+diesel::table! {
+    roles (id) {
+        id -> Int4,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    sessions (token_hash) {
+        token_hash -> Text,
+        user_id -> Int4,
+        created -> Timestamp,
+        expires -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::StockMovementReason;
+
+    stock_movements (id) {
+        id -> Int4,
+        product_id -> Int4,
+        quantity -> Float8,
+        reason -> StockMovementReason,
+        order_id -> Nullable<Int4>,
+        user_id -> Nullable<Int4>,
+        occurred_at -> Timestamp,
     }
 }
 
 diesel::table! {
     suppliers (id) {
         id -> Int4,
-        products -> Array<Nullable<Int4>>,
         name -> Text,
         phone_number -> Nullable<Text>,
         email -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role_id) {
+        user_id -> Int4,
+        role_id -> Int4,
     }
 }
 
@@ -90,22 +186,75 @@ diesel::table! {
         name -> Text,
         email -> Text,
         password -> Varchar,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        event -> Text,
+        payload -> Jsonb,
+        attempts -> Int4,
+        next_attempt -> Nullable<Timestamp>,
+        delivered -> Nullable<Timestamp>,
+        last_status -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Int4,
+        url -> Text,
+        secret -> Text,
+        events -> Array<Nullable<Text>>,
+        created_by -> Nullable<Int4>,
+        created -> Timestamp,
     }
 }
 
+diesel::joinable!(api_keys -> users (created_by));
+diesel::joinable!(audit_log -> users (user_id));
+diesel::joinable!(idempotency_keys -> users (user_id));
 diesel::joinable!(pending_orders -> products (product_id));
-diesel::joinable!(permissions -> users (user_id));
 diesel::joinable!(preferences -> users (user_id));
+diesel::joinable!(product_categories -> categories (category_id));
+diesel::joinable!(product_categories -> products (product_id));
+diesel::joinable!(product_suppliers -> products (product_id));
+diesel::joinable!(product_suppliers -> suppliers (supplier_id));
+diesel::joinable!(products -> brands (brand_id));
 diesel::joinable!(received_orders -> products (product_id));
+diesel::joinable!(role_permissions -> roles (role_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(stock_movements -> products (product_id));
+diesel::joinable!(stock_movements -> users (user_id));
+diesel::joinable!(user_roles -> roles (role_id));
+diesel::joinable!(user_roles -> users (user_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+diesel::joinable!(webhooks -> users (created_by));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_log,
     brands,
     categories,
+    idempotency_keys,
     pending_orders,
-    permissions,
     preferences,
+    product_categories,
+    product_suppliers,
     products,
     received_orders,
+    role_permissions,
+    roles,
+    sessions,
+    stock_movements,
     suppliers,
+    user_roles,
     users,
+    webhook_deliveries,
+    webhooks,
 );
@@ -0,0 +1,32 @@
+use crate::database::Pool;
+use crate::error::ApiResult;
+use diesel_async::scoped_futures::ScopedBoxFuture;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+
+/// Runs `operation` in a database transaction on a pooled connection. If it
+/// returns an error, nothing it did is kept.
+///
+/// Model methods all take a `&mut AsyncPgConnection`, so any number of them can
+/// be composed into one atomic operation:
+///
+/// ```ignore
+/// services::transaction(&state.db_pool, |conn| {
+///     async move {
+///         let product = Product::get(conn, id).await?;
+///         product.delete(conn, actor).await?;
+///         Ok(())
+///     }
+///     .scope_boxed()
+/// })
+/// .await
+/// ```
+pub async fn transaction<'a, T, F>(pool: &Pool, operation: F) -> ApiResult<T>
+where
+    F: for<'r> FnOnce(&'r mut AsyncPgConnection) -> ScopedBoxFuture<'a, 'r, ApiResult<T>>
+        + Send
+        + 'a,
+    T: Send + 'a,
+{
+    let mut conn = pool.get().await?;
+    conn.as_mut().transaction(operation).await
+}
@@ -0,0 +1,132 @@
+use crate::models::StockMovementReason;
+use bigdecimal::{BigDecimal, Signed};
+use std::borrow::Cow;
+use validator::ValidationError;
+
+const MIN_PHONE_DIGITS: usize = 7;
+const MAX_PHONE_DIGITS: usize = 15;
+
+fn invalid(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Borrowed(message));
+    error
+}
+
+/// A GTIN such as a UPC-A, EAN-8, EAN-13 or GTIN-14, whose last digit is a
+/// check digit over the others.
+pub fn upc(upc: &str) -> Result<(), ValidationError> {
+    if !matches!(upc.len(), 8 | 12 | 13 | 14) || !upc.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid("upc", "must be 8, 12, 13 or 14 digits"));
+    }
+    let digits: Vec<u32> = upc.bytes().map(|b| u32::from(b - b'0')).collect();
+    let (check, payload) = digits.split_last().unwrap();
+    // Weighted 3, 1, 3, ... starting from the digit next to the check digit
+    let sum: u32 = payload
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit * 3 } else { *digit })
+        .sum();
+    if (10 - sum % 10) % 10 != *check {
+        return Err(invalid("upc_check_digit", "has the wrong check digit"));
+    }
+    Ok(())
+}
+
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(invalid("blank", "must not be empty"));
+    }
+    Ok(())
+}
+
+pub fn price(price: &BigDecimal) -> Result<(), ValidationError> {
+    if price.is_negative() {
+        return Err(invalid("negative", "must not be negative"));
+    }
+    Ok(())
+}
+
+/// Digits with an optional leading `+`, grouped by spaces, dashes, dots or
+/// parentheses.
+pub fn phone_number(number: &str) -> Result<(), ValidationError> {
+    let number = number.strip_prefix('+').unwrap_or(number);
+    let valid_chars = number
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')'));
+    let digits = number.chars().filter(char::is_ascii_digit).count();
+    if !valid_chars || !(MIN_PHONE_DIGITS..=MAX_PHONE_DIGITS).contains(&digits) {
+        return Err(invalid(
+            "phone_number",
+            "must be a phone number of 7 to 15 digits",
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that no more was damaged than was received. Reported against the
+/// `damaged` field.
+pub fn received_amounts(actually_received: f64, damaged: f64) -> Result<(), ValidationError> {
+    if damaged > actually_received {
+        let mut error = invalid(
+            "damaged_exceeds_received",
+            "must not exceed actually_received",
+        );
+        error.add_param(Cow::Borrowed("field"), &"damaged");
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Sales and damage take stock away and receipts and returns add it, so their
+/// quantities must be negative or positive. Reported against the `quantity`
+/// field.
+pub fn movement_quantity(
+    reason: StockMovementReason,
+    quantity: f64,
+) -> Result<(), ValidationError> {
+    let mut error = match reason {
+        StockMovementReason::Sale | StockMovementReason::Damage if quantity >= 0.0 => {
+            invalid("positive", "must be negative for sales and damage")
+        }
+        StockMovementReason::Receipt | StockMovementReason::Return if quantity <= 0.0 => {
+            invalid("negative", "must be positive for receipts and returns")
+        }
+        _ => return Ok(()),
+    };
+    error.add_param(Cow::Borrowed("field"), &"quantity");
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upcs_need_a_matching_check_digit() {
+        assert!(upc("036000291452").is_ok());
+        assert!(upc("4006381333931").is_ok());
+        assert!(upc("96385074").is_ok());
+        assert!(upc("036000291453").is_err());
+        assert!(upc("03600029145").is_err());
+        assert!(upc("03600029145a").is_err());
+    }
+
+    #[test]
+    fn movement_quantities_follow_their_reason() {
+        assert!(movement_quantity(StockMovementReason::Sale, -2.0).is_ok());
+        assert!(movement_quantity(StockMovementReason::Sale, 2.0).is_err());
+        assert!(movement_quantity(StockMovementReason::Damage, 0.0).is_err());
+        assert!(movement_quantity(StockMovementReason::Receipt, -1.0).is_err());
+        assert!(movement_quantity(StockMovementReason::Adjustment, -1.0).is_ok());
+        assert!(movement_quantity(StockMovementReason::Transfer, 1.0).is_ok());
+    }
+
+    #[test]
+    fn phone_numbers_allow_common_separators() {
+        assert!(phone_number("+1 (555) 010-9999").is_ok());
+        assert!(phone_number("555.010.9999").is_ok());
+        assert!(phone_number("123").is_err());
+        assert!(phone_number("call 5550109999").is_err());
+    }
+}
@@ -0,0 +1,373 @@
+use crate::database::Pool;
+use crate::error::{ApiError, ApiResult};
+use crate::models::{Webhook, WebhookDelivery};
+use chrono::{Duration, NaiveDateTime, Utc};
+use hmac::{Hmac, Mac};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use rocket::futures::future::join_all;
+use rocket::tokio::time::{sleep, timeout};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+pub const PRODUCT_CREATED: &str = "product.created";
+pub const PRODUCT_DELETED: &str = "product.deleted";
+pub const PRICE_CHANGED: &str = "product.price_changed";
+pub const BELOW_BUY_LEVEL: &str = "product.below_buy_level";
+pub const ORDER_RECEIVED: &str = "order.received";
+
+pub const ALL: [&str; 5] = [
+    PRODUCT_CREATED,
+    PRODUCT_DELETED,
+    PRICE_CHANGED,
+    BELOW_BUY_LEVEL,
+    ORDER_RECEIVED,
+];
+
+const PRICES: [&str; 3] = [
+    "cost_price_per_unit",
+    "selling_price_per_unit",
+    "sale_price",
+];
+
+/// Attempts before a delivery is given up on. Retries wait 30 seconds, then
+/// twice as long each time, about half an hour before the last one.
+const MAX_ATTEMPTS: i32 = 8;
+const FIRST_RETRY_SECONDS: i64 = 30;
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// Longer than a batch can take, so a delivery is only retried by another
+/// worker if the one that claimed it died.
+const LEASE_MINUTES: i64 = 5;
+const BATCH_SIZE: i64 = 20;
+
+pub type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+/// A client for both http:// and https:// webhook URLs.
+pub fn http_client() -> HttpClient {
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+pub fn is_known(event: &str) -> bool {
+    ALL.contains(&event)
+}
+
+/// Checks that deliveries can be sent to `url`.
+pub fn validate_url(url: &str) -> ApiResult<()> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|_| ApiError::Validation(format!("Invalid URL {}", url)))?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none() {
+        return Err(ApiError::Validation(String::from(
+            "Webhook URLs must be absolute http:// or https:// URLs",
+        )));
+    }
+    Ok(())
+}
+
+fn below_buy_level(product: Option<&Value>) -> bool {
+    product.is_some_and(|product| {
+        match (product["amount"].as_f64(), product["buy_level"].as_f64()) {
+            (Some(amount), Some(buy_level)) => amount < buy_level,
+            _ => false,
+        }
+    })
+}
+
+fn received(order: Option<&Value>) -> bool {
+    order.is_some_and(|order| !order["received"].is_null())
+}
+
+/// The webhook events a change to `entity` from `before` to `after` raises,
+/// with their payloads.
+pub fn events(
+    entity: &str,
+    before: Option<&Value>,
+    after: Option<&Value>,
+) -> Vec<(&'static str, Value)> {
+    let mut events = Vec::new();
+    match entity {
+        "product" => {
+            match (before, after) {
+                (None, Some(_)) => events.push(PRODUCT_CREATED),
+                (Some(_), None) => events.push(PRODUCT_DELETED),
+                (Some(before), Some(after)) => {
+                    if PRICES.iter().any(|price| before[price] != after[price]) {
+                        events.push(PRICE_CHANGED);
+                    }
+                }
+                (None, None) => {}
+            }
+            // Only on crossing it, not on every change while stock stays low
+            if !below_buy_level(before) && below_buy_level(after) {
+                events.push(BELOW_BUY_LEVEL);
+            }
+        }
+        "received_order" if !received(before) && received(after) => events.push(ORDER_RECEIVED),
+        _ => {}
+    }
+    let occurred_at = Utc::now().naive_utc();
+    events
+        .into_iter()
+        .map(|event| {
+            let payload = json!({
+                "event": event,
+                "occurred_at": occurred_at,
+                "data": after.or(before),
+                "previous": before,
+            });
+            (event, payload)
+        })
+        .collect()
+}
+
+/// The `X-Webhook-Signature` header for `body` sent at `timestamp`: an
+/// HMAC-SHA256 of `"{timestamp}.{body}"` keyed with the webhook's secret.
+/// Receivers should compare it in constant time and reject old timestamps.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// POSTs the delivery, returning the response status if there was one and an
+/// error unless it was a success.
+async fn send(
+    client: &HttpClient,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+) -> (Option<i32>, Option<String>) {
+    let body = serde_json::to_vec(&delivery.payload).expect("JSON values always serialize");
+    let timestamp = Utc::now().timestamp();
+    let request = Request::post(webhook.url.as_str())
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Event", delivery.event.as_str())
+        .header("X-Webhook-Delivery", delivery.id.to_string())
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header(
+            "X-Webhook-Signature",
+            signature(&webhook.secret, timestamp, &body),
+        )
+        .body(Body::from(body));
+    let request = match request {
+        Ok(request) => request,
+        Err(error) => return (None, Some(error.to_string())),
+    };
+    match timeout(REQUEST_TIMEOUT, client.request(request)).await {
+        Err(_) => (None, Some(String::from("Timed out"))),
+        Ok(Err(error)) => (None, Some(error.to_string())),
+        Ok(Ok(response)) => {
+            let status = response.status();
+            if status.is_success() {
+                (Some(status.as_u16().into()), None)
+            } else {
+                (
+                    Some(status.as_u16().into()),
+                    Some(format!("Responded with {}", status)),
+                )
+            }
+        }
+    }
+}
+
+/// When to retry after the `attempts`th attempt failed, if at all.
+fn retry_at(attempts: i32) -> Option<NaiveDateTime> {
+    (attempts < MAX_ATTEMPTS).then(|| {
+        Utc::now().naive_utc() + Duration::seconds(FIRST_RETRY_SECONDS << (attempts - 1).max(0))
+    })
+}
+
+/// Attempts a delivery now and records how it went. A connection is only held
+/// around the request, not during it.
+pub async fn deliver(
+    pool: &Pool,
+    client: &HttpClient,
+    delivery: &WebhookDelivery,
+) -> ApiResult<WebhookDelivery> {
+    let webhook = {
+        let mut conn = pool.get().await?;
+        Webhook::get(conn.as_mut(), delivery.webhook_id).await?
+    };
+    let (status, error) = send(client, &webhook, delivery).await;
+    let next_attempt = match error {
+        None => None,
+        Some(_) => retry_at(delivery.attempts + 1),
+    };
+    let mut conn = pool.get().await?;
+    Ok(
+        WebhookDelivery::record_attempt(conn.as_mut(), delivery.id, status, error, next_attempt)
+            .await?,
+    )
+}
+
+/// Attempts every delivery that is due, returning how many there were.
+pub async fn deliver_due(pool: &Pool, client: &HttpClient) -> ApiResult<usize> {
+    let due = {
+        let mut conn = pool.get().await?;
+        WebhookDelivery::claim_due(conn.as_mut(), Duration::minutes(LEASE_MINUTES), BATCH_SIZE)
+            .await?
+    };
+    let attempts = due.iter().map(|delivery| deliver(pool, client, delivery));
+    for result in join_all(attempts).await {
+        result?;
+    }
+    Ok(due.len())
+}
+
+/// Sends due deliveries until the server stops.
+pub async fn run(pool: Pool) {
+    let client = http_client();
+    loop {
+        match deliver_due(&pool, &client).await {
+            // Keep going while there is a backlog
+            Ok(delivered) if delivered as i64 == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(error) => eprintln!("Delivering webhooks failed: {:?}", error),
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Manager;
+    use crate::models::tests::test_actor;
+    use crate::models::{generate_token, ProductBuilder};
+    use bigdecimal::BigDecimal;
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use rocket::tokio::net::TcpListener;
+
+    /// Stands in for a receiver: takes one request, answers it with `status`
+    /// and returns its headers and body.
+    async fn receive(listener: &TcpListener, status: u16) -> (String, String) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        let (head, body) = loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length:")?
+                            .trim()
+                            .parse()
+                            .ok()
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break (head.to_lowercase(), body.to_string());
+                }
+            }
+            assert_ne!(read, 0, "connection closed mid-request");
+        };
+        let response = format!("HTTP/1.1 {} Stand-in\r\ncontent-length: 0\r\n\r\n", status);
+        stream.write_all(response.as_bytes()).await.unwrap();
+        (head, body)
+    }
+
+    fn header<'a>(head: &'a str, name: &str) -> &'a str {
+        head.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .unwrap()
+            .trim()
+    }
+
+    #[test]
+    fn urls_must_be_absolute_http_or_https() {
+        assert!(validate_url("https://example.com/hook").is_ok());
+        assert!(validate_url("http://127.0.0.1:8080/hook").is_ok());
+        assert!(validate_url("ftp://example.com/hook").is_err());
+        assert!(validate_url("/hook").is_err());
+    }
+
+    #[test]
+    fn price_changes_and_low_stock_raise_events() {
+        let before = json!({ "amount": 5.0, "buy_level": 4.0, "selling_price_per_unit": "2.00" });
+        let after = json!({ "amount": 3.0, "buy_level": 4.0, "selling_price_per_unit": "2.50" });
+        let raised = |before: &Value, after: &Value| {
+            events("product", Some(before), Some(after))
+                .into_iter()
+                .map(|(event, _)| event)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(raised(&before, &after), [PRICE_CHANGED, BELOW_BUY_LEVEL]);
+        // Still below it, so nothing new to tell
+        assert!(raised(&after, &after).is_empty());
+        assert!(events("supplier", None, Some(&after)).is_empty());
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs a database"]
+    async fn failed_deliveries_are_retried_and_signed() {
+        let pool = Pool::builder(Manager {}).build().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let mut conn = pool.get().await.unwrap();
+        let actor = test_actor(conn.as_mut()).await;
+        let webhook = Webhook::create(conn.as_mut(), actor.id, url, vec![PRODUCT_CREATED.into()])
+            .await
+            .unwrap();
+        let product = ProductBuilder::new(
+            &generate_token(),
+            "Webhook product",
+            false,
+            BigDecimal::from(1),
+            BigDecimal::from(2),
+        )
+        .build(conn.as_mut(), actor.id)
+        .await
+        .unwrap();
+        // Other tests may be creating products at the same time
+        let delivery = WebhookDelivery::for_webhook(conn.as_mut(), webhook.id, None, 1000)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|delivery| delivery.payload["data"]["id"] == product)
+            .unwrap();
+        drop(conn);
+        let client = http_client();
+
+        let (failed, _) =
+            rocket::tokio::join!(deliver(&pool, &client, &delivery), receive(&listener, 500));
+        let failed = failed.unwrap();
+        assert_eq!(failed.attempts, 1);
+        assert_eq!(failed.last_status, Some(500));
+        assert!(failed.delivered.is_none());
+        assert!(failed.next_attempt.unwrap() > Utc::now().naive_utc());
+
+        let (delivered, (head, body)) =
+            rocket::tokio::join!(deliver(&pool, &client, &failed), receive(&listener, 204));
+        let delivered = delivered.unwrap();
+        assert_eq!(delivered.attempts, 2);
+        assert!(delivered.delivered.is_some());
+        assert!(delivered.next_attempt.is_none());
+
+        assert_eq!(header(&head, "x-webhook-event"), PRODUCT_CREATED);
+        let timestamp = header(&head, "x-webhook-timestamp").parse().unwrap();
+        assert_eq!(
+            header(&head, "x-webhook-signature"),
+            signature(&webhook.secret, timestamp, body.as_bytes())
+        );
+        let payload: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(payload["data"]["name"], "Webhook product");
+
+        let mut conn = pool.get().await.unwrap();
+        Webhook::delete(conn.as_mut(), actor.id, webhook.id)
+            .await
+            .unwrap();
+    }
+}